@@ -0,0 +1,124 @@
+use crate::data;
+use crate::interaction::{mentions, words};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+// Identifier system for the allergy records kept in the patient registry
+pub const SYSTEM: &str = "urn:charcot:allergy";
+
+// Values accepted after `severity` in `register allergy`
+pub const CRITICALITIES: &[&str] = &["low", "high", "unable-to-assess"];
+
+// How dangerous another exposure could be, as in FHIR
+// AllergyIntolerance.criticality
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Criticality {
+    Low,
+    High,
+    UnableToAssess,
+}
+
+impl Criticality {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "low" => Some(Criticality::Low),
+            "high" => Some(Criticality::High),
+            "unable-to-assess" => Some(Criticality::UnableToAssess),
+            _ => None,
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            Criticality::Low => "low",
+            Criticality::High => "high",
+            Criticality::UnableToAssess => "unable-to-assess",
+        }
+    }
+}
+
+impl fmt::Display for Criticality {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.code())
+    }
+}
+
+// An allergy recorded for a patient
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Allergy {
+    // <MRN>-A<n>, so exporting the allergy again does not duplicate it
+    pub id: String,
+    pub substance: String,
+    pub criticality: Criticality,
+    pub recorded_at: String,
+}
+
+// A recorded allergy that a drug matches
+pub struct Conflict<'a> {
+    pub allergy: &'a Allergy,
+    // The drug class the match went through, when the drug is not named in
+    // the allergy itself
+    pub class: Option<String>,
+}
+
+impl Conflict<'_> {
+    // Unless the patient is known to react mildly, prescribing needs an
+    // override reason
+    pub fn blocks(&self) -> bool {
+        self.allergy.criticality != Criticality::Low
+    }
+}
+
+impl fmt::Display for Conflict<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "allergy to {} ({} criticality", self.allergy.substance, self.allergy.criticality)?;
+        if let Some(class) = &self.class {
+            write!(f, ", drug class {}", class)?;
+        }
+        write!(f, ")")
+    }
+}
+
+lazy_static::lazy_static! {
+    // (class, drug) pairs
+    static ref CLASSES: Vec<(String, Vec<String>)> = data::rows(data::DRUG_CLASSES)
+        .filter_map(|fields| {
+            let [class, drug] = fields.as_slice() else {
+                return None;
+            };
+            Some((words(class).join(" "), words(drug)))
+        })
+        .collect();
+}
+
+// Allergies a drug matches, either by name or through one of its drug
+// classes. "penicillins" matches the penicillin class as well.
+pub fn conflicts<'a>(drug: &str, allergies: &'a [Allergy]) -> Vec<Conflict<'a>> {
+    let prescribed = words(drug);
+    let classes: Vec<&str> = CLASSES
+        .iter()
+        .filter(|(_, member)| mentions(&prescribed, member))
+        .map(|(class, _)| class.as_str())
+        .collect();
+
+    let mut found = Vec::new();
+    for allergy in allergies {
+        let substance = words(&allergy.substance);
+        if mentions(&prescribed, &substance) {
+            found.push(Conflict { allergy, class: None });
+            continue;
+        }
+        let substance = substance.join(" ");
+        let class = classes
+            .iter()
+            .find(|class| substance == **class || substance == format!("{}s", class));
+        if let Some(class) = class {
+            found.push(Conflict {
+                allergy,
+                class: Some(class.to_string()),
+            });
+        }
+    }
+    found
+}
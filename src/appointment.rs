@@ -1,10 +1,28 @@
-use std::collections::HashMap;
+use chrono::{DateTime, Duration};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use crate::clinical::{ClinicalAction, DiagnosisStatus, Prescription};
+use crate::clock;
+use crate::draft::{Draft, DraftStore};
+use crate::fhir::{self, Record};
+use crate::icd10::Icd10Code;
+use crate::interaction::Interaction;
+use crate::patient::Patient;
+use crate::sections;
+use crate::store::EncounterStore;
+use crate::template;
+use crate::validation::{self, Rule, Severity};
 
 pub struct Appointment {
     pub id: usize,
+    // Medical record number in the patient registry
+    pub patient_id: Option<String>,
     pub patient_name: String,
     pub time: String,
     pub reason: String,
+    // Telehealth visit, where no physical exam can be done
+    pub virtual_visit: bool,
 }
 
 pub struct AppointmentManager {
@@ -12,68 +30,98 @@ pub struct AppointmentManager {
 }
 
 impl AppointmentManager {
-    pub fn new() -> Self {
+    pub fn new(appointments: Vec<Appointment>) -> Self {
+        Self { appointments }
+    }
+
+    // Load the day's schedule from a JSON, CSV or FHIR Bundle file
+    pub fn from_file(path: &Path) -> Result<Self, String> {
+        crate::schedule::load(path).map(Self::new)
+    }
+
+    // Built-in demo schedule, used with --demo when no schedule file is available
+    pub fn demo() -> Self {
         // Create a list of 10 appointments with varied information
         let appointments = vec![
             Appointment {
                 id: 1,
+                patient_id: Some("MRN-000001".to_string()),
                 patient_name: "Jane Doe".to_string(),
                 time: "09:00".to_string(),
                 reason: "Annual checkup".to_string(),
+                virtual_visit: false,
             },
             Appointment {
                 id: 2,
+                patient_id: Some("MRN-000002".to_string()),
                 patient_name: "John Smith".to_string(),
                 time: "09:30".to_string(),
                 reason: "Follow-up after surgery".to_string(),
+                virtual_visit: false,
             },
             Appointment {
                 id: 3,
+                patient_id: Some("MRN-000003".to_string()),
                 patient_name: "Mary Johnson".to_string(),
                 time: "10:15".to_string(),
                 reason: "Chronic headache".to_string(),
+                virtual_visit: false,
             },
             Appointment {
                 id: 4,
+                patient_id: Some("MRN-000004".to_string()),
                 patient_name: "Robert Brown".to_string(),
                 time: "11:00".to_string(),
                 reason: "Diabetes management".to_string(),
+                virtual_visit: false,
             },
             Appointment {
                 id: 5,
+                patient_id: Some("MRN-000005".to_string()),
                 patient_name: "Patricia Davis".to_string(),
                 time: "11:45".to_string(),
                 reason: "Skin rash".to_string(),
+                virtual_visit: false,
             },
             Appointment {
                 id: 6,
+                patient_id: Some("MRN-000006".to_string()),
                 patient_name: "Michael Wilson".to_string(),
                 time: "13:30".to_string(),
                 reason: "Hypertension follow-up".to_string(),
+                virtual_visit: false,
             },
             Appointment {
                 id: 7,
+                patient_id: Some("MRN-000007".to_string()),
                 patient_name: "Elizabeth Martinez".to_string(),
                 time: "14:15".to_string(),
                 reason: "Pregnancy checkup".to_string(),
+                virtual_visit: false,
             },
             Appointment {
                 id: 8,
+                patient_id: Some("MRN-000008".to_string()),
                 patient_name: "James Anderson".to_string(),
                 time: "15:00".to_string(),
                 reason: "Lower back pain".to_string(),
+                virtual_visit: false,
             },
             Appointment {
                 id: 9,
+                patient_id: Some("MRN-000009".to_string()),
                 patient_name: "Jennifer Thomas".to_string(),
                 time: "15:45".to_string(),
                 reason: "Anxiety management".to_string(),
+                virtual_visit: true,
             },
             Appointment {
                 id: 10,
+                patient_id: Some("MRN-000010".to_string()),
                 patient_name: "Charles Jackson".to_string(),
                 time: "16:30".to_string(),
                 reason: "Prescription renewal".to_string(),
+                virtual_visit: false,
             },
         ];
         
@@ -84,124 +132,785 @@ impl AppointmentManager {
         &self.appointments
     }
     
+    pub fn appointments_mut(&mut self) -> &mut [Appointment] {
+        &mut self.appointments
+    }
+    
+    // Appointments are looked up by their id, the number shown by `fetch
+    // appointments` and referenced from exported encounters
     pub fn get_appointment(&self, id: usize) -> Option<&Appointment> {
-        if id >= 1 && id <= self.appointments.len() {
-            Some(&self.appointments[id - 1])
-        } else {
-            None
+        self.appointments.iter().find(|appointment| appointment.id == id)
+    }
+}
+
+// One dictated piece of a section, with the time it was registered
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SectionEntry {
+    pub text: String,
+    pub recorded_at: String,
+    // ICD-10 code of a diagnostic hypothesis
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code: Option<Icd10Code>,
+    // Verification status of a diagnostic hypothesis; provisional when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<DiagnosisStatus>,
+    // Condition a diagnostic hypothesis was exported as, when it was read
+    // back from a committed encounter
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resource_id: Option<String>,
+}
+
+impl SectionEntry {
+    pub fn new(text: String) -> Self {
+        Self {
+            text,
+            recorded_at: clock::now().to_rfc3339(),
+            code: None,
+            status: None,
+            resource_id: None,
         }
     }
 }
 
+// Drafts written before sections kept several entries store a single string
+// per section; read those as one entry.
+fn deserialize_sections<'de, D>(deserializer: D) -> Result<HashMap<String, Vec<SectionEntry>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Stored {
+        Entries(Vec<SectionEntry>),
+        Text(String),
+    }
+
+    let stored = HashMap::<String, Stored>::deserialize(deserializer)?;
+    Ok(stored
+        .into_iter()
+        .map(|(section, stored)| match stored {
+            Stored::Entries(entries) => (section, entries),
+            Stored::Text(text) => (
+                section,
+                vec![SectionEntry {
+                    text,
+                    recorded_at: String::new(),
+                    code: None,
+                    status: None,
+                    resource_id: None,
+                }],
+            ),
+        })
+        .collect())
+}
+
+// A stretch of time an open encounter was set aside, e.g. while the physician
+// switched to another patient
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Pause {
+    pub from: String,
+    // None while the encounter is still set aside
+    pub to: Option<String>,
+}
+
+// Committed encounter that an encounter reopened with `open encounter` amends
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Amendment {
+    // Number of the committed encounter
+    pub encounter_id: usize,
+    // Version the addendum is committed as
+    pub version: u32,
+    // File the encounter was reopened from
+    pub opened_from: String,
+    // Period of the original visit, kept on every version
+    pub period_start: String,
+    pub period_end: Option<String>,
+    // Active minutes of the original visit
+    #[serde(default)]
+    pub length_minutes: Option<i64>,
+    // Id of the Encounter resource the addendum updates; empty when the file
+    // has none, as in bare Encounters from before Bundles were written
+    #[serde(default)]
+    pub resource_id: String,
+    // Conditions, orders and interactions the amended version exported, as
+    // <type>/<id>, so the addendum can delete the ones it no longer has
+    #[serde(default)]
+    pub exported: Vec<String>,
+}
+
 // New Encounter structure to track encounter data
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Encounter {
     pub appointment_id: usize,
+    #[serde(default)]
+    pub patient_id: Option<String>,
     pub patient_name: String,
     pub reason: String,
-    pub sections: HashMap<String, String>,
-    pub actions: Vec<String>,
+    // Entries of each section in the order they were registered
+    #[serde(deserialize_with = "deserialize_sections")]
+    pub sections: HashMap<String, Vec<SectionEntry>>,
+    pub actions: Vec<ClinicalAction>,
+    // Drug interactions found as prescriptions were written
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub interactions: Vec<Interaction>,
     pub status: String,
+    #[serde(default)]
+    pub virtual_visit: bool,
+    // Validation warnings the physician chose to commit over
+    #[serde(default)]
+    pub overridden_warnings: Vec<String>,
+    // When `attend appointment` started the encounter and when it was
+    // committed, with the times it was set aside in between
+    #[serde(default)]
+    pub started_at: String,
+    #[serde(default)]
+    pub ended_at: Option<String>,
+    #[serde(default)]
+    pub pauses: Vec<Pause>,
+    // Set when this is an addendum to a committed encounter
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub amends: Option<Amendment>,
 }
 
 impl Encounter {
-    pub fn new(appointment_id: usize, patient_name: String, reason: String) -> Self {
+    pub fn new(appointment: &Appointment) -> Self {
         Self {
-            appointment_id,
-            patient_name,
-            reason,
+            appointment_id: appointment.id,
+            patient_id: appointment.patient_id.clone(),
+            patient_name: appointment.patient_name.clone(),
+            reason: appointment.reason.clone(),
             sections: HashMap::new(),
             actions: Vec::new(),
+            interactions: Vec::new(),
             status: "in-progress".to_string(),
+            virtual_visit: appointment.virtual_visit,
+            overridden_warnings: Vec::new(),
+            started_at: clock::now().to_rfc3339(),
+            ended_at: None,
+            pauses: Vec::new(),
+            amends: None,
         }
     }
     
-    pub fn add_section(&mut self, section: String, details: String) {
-        self.sections.insert(section, details);
+    // Append an entry to a section; returns how many entries it now has
+    pub fn add_section(&mut self, section: String, entry: SectionEntry) -> usize {
+        let entries = self.sections.entry(section).or_default();
+        entries.push(entry);
+        entries.len()
     }
     
-    pub fn add_action(&mut self, action: String) {
+    // Replace the most recent entry of a section, e.g. to fix a dictation slip.
+    // An amended diagnosis keeps its verification status.
+    pub fn amend_section(&mut self, section: &str, mut entry: SectionEntry) -> Result<SectionEntry, String> {
+        match self.sections.get_mut(section).and_then(|entries| entries.last_mut()) {
+            Some(last) => {
+                entry.status = entry.status.or(last.status);
+                Ok(std::mem::replace(last, entry))
+            },
+            None => Err(format!("Nothing registered in the {} section to amend", section)),
+        }
+    }
+    
+    // Replace every entry of a section with a single new one; returns how many
+    // entries were dropped
+    pub fn replace_section(&mut self, section: String, entry: SectionEntry) -> usize {
+        self.sections
+            .insert(section, vec![entry])
+            .map_or(0, |entries| entries.len())
+    }
+    
+    // Entries of the catalogue's diagnosis section, in rank order
+    fn diagnoses_mut(&mut self) -> Result<&mut [SectionEntry], String> {
+        let section = sections::diagnosis_section()
+            .ok_or_else(|| "The section catalogue has no diagnosis section".to_string())?;
+        Ok(self.sections.get_mut(&section).map(Vec::as_mut_slice).unwrap_or_default())
+    }
+    
+    // Diagnosis `number` (1-based, in rank order)
+    fn diagnosis_mut(&mut self, number: usize) -> Result<&mut SectionEntry, String> {
+        let diagnoses = self.diagnoses_mut()?;
+        let count = diagnoses.len();
+        match number.checked_sub(1).and_then(|index| diagnoses.get_mut(index)) {
+            Some(diagnosis) => Ok(diagnosis),
+            None if count == 0 => Err("No diagnostic hypotheses registered".to_string()),
+            None => Err(format!("Invalid diagnosis number {} (1-{} registered)", number, count)),
+        }
+    }
+    
+    // Move diagnosis `number` to rank `to`, or to the end when `to` is None.
+    // Returns the moved diagnosis and its new rank.
+    pub fn rank_diagnosis(&mut self, number: usize, to: Option<usize>) -> Result<(SectionEntry, usize), String> {
+        self.diagnosis_mut(number)?;
+        let diagnoses = self.diagnoses_mut()?;
+        let rank = to.unwrap_or(diagnoses.len());
+        if rank == 0 || rank > diagnoses.len() {
+            return Err(format!("Invalid rank {} (1-{} available)", rank, diagnoses.len()));
+        }
+        // Rotating the slice between the two positions moves the diagnosis
+        // and shifts the ones in between by one
+        if rank < number {
+            diagnoses[rank - 1..number].rotate_right(1);
+        } else {
+            diagnoses[number - 1..rank].rotate_left(1);
+        }
+        Ok((diagnoses[rank - 1].clone(), rank))
+    }
+    
+    // Set the verification status of diagnosis `number`
+    pub fn mark_diagnosis(&mut self, number: usize, status: DiagnosisStatus) -> Result<SectionEntry, String> {
+        let diagnosis = self.diagnosis_mut(number)?;
+        diagnosis.status = Some(status);
+        Ok(diagnosis.clone())
+    }
+    
+    // Remove a section entirely; returns how many entries were dropped
+    pub fn clear_section(&mut self, section: &str) -> Result<usize, String> {
+        match self.sections.remove(section) {
+            Some(entries) => Ok(entries.len()),
+            None => Err(format!("The {} section is already empty", section)),
+        }
+    }
+    
+    // Drugs prescribed so far in this encounter
+    pub fn prescribed_drugs(&self) -> Vec<String> {
+        self.actions
+            .iter()
+            .filter_map(|action| match action {
+                ClinicalAction::Prescription(prescription) => Some(prescription.drug.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+    
+    pub fn add_action(&mut self, action: ClinicalAction) {
         self.actions.push(action);
     }
     
     pub fn complete(&mut self) {
+        let now = clock::now().to_rfc3339();
+        self.resume(&now);
+        self.ended_at = Some(now);
         self.status = "completed".to_string();
     }
+    
+    // Set the encounter aside, unless it already is
+    pub fn pause(&mut self, at: &str) {
+        if self.pauses.last().is_none_or(|pause| pause.to.is_some()) {
+            self.pauses.push(Pause {
+                from: at.to_string(),
+                to: None,
+            });
+        }
+    }
+    
+    // Pick the encounter up again after a pause
+    pub fn resume(&mut self, at: &str) {
+        if let Some(pause) = self.pauses.last_mut().filter(|pause| pause.to.is_none()) {
+            pause.to = Some(at.to_string());
+        }
+    }
+    
+    // Time spent on the encounter, leaving out pauses. Runs up to now while
+    // the encounter is open; None for drafts saved before start times were kept.
+    pub fn active_time(&self) -> Option<Duration> {
+        let parse = |time: &str| DateTime::parse_from_rfc3339(time).ok();
+        let start = parse(&self.started_at)?;
+        let end = match &self.ended_at {
+            Some(end) => parse(end)?,
+            None => clock::now(),
+        };
+        let paused = self
+            .pauses
+            .iter()
+            .filter_map(|pause| {
+                let from = parse(&pause.from)?;
+                let to = pause.to.as_deref().and_then(parse).unwrap_or(end);
+                Some(to - from)
+            })
+            .fold(Duration::zero(), |total, pause| total + pause);
+        Some((end - start - paused).max(Duration::zero()))
+    }
+    
+    // Replace a template placeholder in every section entry; returns how many
+    // entries changed
+    pub fn fill_placeholder(&mut self, placeholder: &str, value: &str) -> usize {
+        let mut filled = 0;
+        for entry in self.sections.values_mut().flatten() {
+            if let Some(text) = template::fill(&entry.text, placeholder, value) {
+                entry.text = text;
+                filled += 1;
+            }
+        }
+        filled
+    }
+    
+    // Sections whose entries still hold template placeholders, in catalogue
+    // order, with the placeholders left in each
+    pub fn unfilled_placeholders(&self) -> Vec<(String, Vec<String>)> {
+        let mut unfilled: Vec<(String, Vec<String>)> = self
+            .sections
+            .iter()
+            .map(|(section, entries)| {
+                let mut names: Vec<String> = Vec::new();
+                for name in entries.iter().flat_map(|entry| template::placeholders(&entry.text)) {
+                    if !names.contains(&name) {
+                        names.push(name);
+                    }
+                }
+                (section.clone(), names)
+            })
+            .filter(|(_, names)| !names.is_empty())
+            .collect();
+        unfilled.sort_by_key(|(section, _)| (sections::position(section), section.clone()));
+        unfilled
+    }
+    
+    // Nothing has been registered yet
+    pub fn is_empty(&self) -> bool {
+        self.sections.is_empty() && self.actions.is_empty()
+    }
 }
 
-// Manager for encounters
+// Undo and redo stacks for one encounter. Each step keeps a description of
+// the edit and the encounter as it was on the other side of it.
+#[derive(Default)]
+pub struct EditHistory {
+    undo: Vec<(String, Encounter)>,
+    redo: Vec<(String, Encounter)>,
+}
+
+// How `start_encounter` came by the encounter it made current
+#[derive(Clone, Copy, PartialEq)]
+pub enum Started {
+    New,
+    // The appointment's encounter was already open in this session
+    Open,
+    // Restored from a draft an earlier session left behind
+    Draft,
+}
+
+// Manager for encounters. Several encounters can be open at once, keyed by the
+// appointment number used in `attend appointment N`; commands apply to the
+// current one. Every change is autosaved to a draft until the encounter is
+// committed or discarded.
 pub struct EncounterManager {
-    pub current_encounter: Option<Encounter>,
-    pub encounter_count: usize,
+    pub open_encounters: BTreeMap<usize, Encounter>,
+    pub current: Option<usize>,
+    pub store: EncounterStore,
+    pub drafts: DraftStore,
+    // Edit history of each open encounter, so undo never crosses patients
+    pub history: HashMap<usize, EditHistory>,
+    // Checks run by commit_encounter
+    pub rules: Vec<Rule>,
 }
 
 impl EncounterManager {
-    pub fn new() -> Self {
+    pub fn new(store: EncounterStore, drafts: DraftStore) -> Self {
         Self {
-            current_encounter: None,
-            encounter_count: 0,
+            open_encounters: BTreeMap::new(),
+            current: None,
+            store,
+            drafts,
+            history: HashMap::new(),
+            rules: validation::default_rules(),
         }
     }
     
-    pub fn start_encounter(&mut self, appointment_id: usize, patient_name: String, reason: String) -> &Encounter {
-        let encounter = Encounter::new(appointment_id, patient_name, reason);
-        self.current_encounter = Some(encounter);
-        self.current_encounter.as_ref().unwrap()
+    // Persist the current encounter's draft after a change
+    fn save_draft(&self) -> Result<(), String> {
+        match self.current.and_then(|number| Some((number, self.open_encounters.get(&number)?))) {
+            Some((number, encounter)) => self.drafts.save(number, encounter),
+            None => Ok(()),
+        }
     }
     
-    pub fn add_section(&mut self, section: String, details: String) -> Result<(), String> {
-        match &mut self.current_encounter {
-            Some(encounter) => {
-                encounter.add_section(section, details);
-                Ok(())
-            },
-            None => Err("No active encounter".to_string()),
+    // Make `number` the current encounter, pausing the one it takes over from
+    fn set_current(&mut self, number: usize) -> Result<(), String> {
+        let now = clock::now().to_rfc3339();
+        if let Some(previous) = self.current.filter(|previous| *previous != number) {
+            if let Some(encounter) = self.open_encounters.get_mut(&previous) {
+                encounter.pause(&now);
+                self.drafts.save(previous, encounter)?;
+            }
         }
+        self.current = Some(number);
+        if let Some(encounter) = self.open_encounters.get_mut(&number) {
+            encounter.resume(&now);
+        }
+        self.save_draft()
     }
     
-    pub fn add_action(&mut self, action: String) -> Result<(), String> {
-        match &mut self.current_encounter {
-            Some(encounter) => {
-                encounter.add_action(action);
-                Ok(())
-            },
-            None => Err("No active encounter".to_string()),
-        }
-    }
-    
-    pub fn commit_encounter(&mut self) -> Result<String, String> {
-        match &mut self.current_encounter {
-            Some(encounter) => {
-                encounter.complete();
-                
-                self.encounter_count += 1;
-                let filename = format!("encounter_{}.med", self.encounter_count);
-                
-                // Convert to FHIR format
-                let fhir_encounter = crate::fhir::create_encounter(
-                    self.encounter_count.to_string(),
-                    encounter.patient_name.clone(),
-                    encounter.reason.clone(),
-                    &encounter.sections,
-                    &encounter.actions,
-                );
-                
-                // Serialize to JSON
-                match serde_json::to_string_pretty(&fhir_encounter) {
-                    Ok(json) => {
-                        // Write to file
-                        match std::fs::write(&filename, json) {
-                            Ok(_) => {
-                                let result = filename.clone();
-                                self.current_encounter = None;
-                                Ok(result)
-                            },
-                            Err(e) => Err(format!("Failed to write file: {}", e)),
-                        }
-                    },
-                    Err(e) => Err(format!("Failed to serialize encounter: {}", e)),
-                }
-            },
-            None => Err("No active encounter to commit".to_string()),
+    // Open an autosaved encounter again. The time since the draft was last
+    // saved counts as a pause.
+    fn restore(&mut self, mut draft: Draft) {
+        if !draft.saved_at.is_empty() {
+            draft.encounter.pause(&draft.saved_at);
+        }
+        self.open_encounters.insert(draft.appointment_number, draft.encounter);
+    }
+    
+    // Reopen the encounters autosaved by an earlier session. Drafts for
+    // appointments that are already open in this session are left alone.
+    // Returns the appointments resumed and the drafts that could not be read.
+    pub fn resume_drafts(&mut self) -> Result<(Vec<usize>, Vec<String>), String> {
+        let (drafts, skipped) = self.drafts.load_all()?;
+        let mut resumed = Vec::new();
+        for draft in drafts {
+            if self.open_encounters.contains_key(&draft.appointment_number) {
+                continue;
+            }
+            resumed.push(draft.appointment_number);
+            self.restore(draft);
+        }
+        if let (None, Some(&first)) = (self.current, resumed.first()) {
+            self.set_current(first)?;
+        }
+        Ok((resumed, skipped))
+    }
+    
+    // Start an encounter for the appointment, or return to it if it is already
+    // open. A draft an earlier session left for the appointment is restored
+    // rather than replaced by an empty encounter.
+    pub fn start_encounter(
+        &mut self,
+        number: usize,
+        appointment: &Appointment,
+    ) -> Result<(&Encounter, Started), String> {
+        let started = if self.open_encounters.contains_key(&number) {
+            Started::Open
+        } else if let Some(mut draft) = self.drafts.load(number)? {
+            // Drafts saved before patients were registered only carry a name
+            if draft.encounter.patient_id.is_none() {
+                draft.encounter.patient_id = appointment.patient_id.clone();
+            }
+            self.restore(draft);
+            Started::Draft
+        } else {
+            self.open_encounters.insert(number, Encounter::new(appointment));
+            Started::New
+        };
+        self.set_current(number)?;
+        Ok((&self.open_encounters[&number], started))
+    }
+    
+    // Reopen a committed encounter file from the output directory as an
+    // addendum. It is edited like any other encounter and committed as the
+    // next version of the same encounter number; the file itself is kept.
+    pub fn open_committed(&mut self, file: &str, schedule: &AppointmentManager) -> Result<&Encounter, String> {
+        let (id, version) = EncounterStore::parse_name(file)
+            .ok_or_else(|| format!("\"{}\" is not an encounter file name (encounter_<number>.med)", file))?;
+        let latest = self.store.latest_version(id)?.unwrap_or(version);
+        if latest > version {
+            return Err(format!(
+                "{} has been amended since; open {} instead",
+                file,
+                EncounterStore::file_name(id, latest)
+            ));
+        }
+        
+        let path = self.store.path(id, version);
+        let record = Record::read(&path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        let mut encounter = fhir::read_encounter(&record)?;
+        // Files from before encounters linked their appointment are matched to
+        // the patient's appointment in the schedule
+        let appointment = match encounter.appointment_id {
+            0 => schedule
+                .get_appointments()
+                .iter()
+                .find(|appointment| appointment.patient_name.eq_ignore_ascii_case(&encounter.patient_name)),
+            number => schedule.get_appointment(number),
+        };
+        // The reopened encounter is keyed by its appointment id, like one
+        // started with `attend appointment`, so that id must not be another
+        // patient's appointment in today's schedule
+        if let Some(appointment) = appointment {
+            let same_patient = match (&encounter.patient_id, &appointment.patient_id) {
+                (Some(id), Some(appointment_id)) => id == appointment_id,
+                _ => appointment.patient_name.eq_ignore_ascii_case(&encounter.patient_name),
+            };
+            if !same_patient {
+                return Err(format!(
+                    "{} belongs to appointment {}, which is {}'s appointment in this schedule; \
+                     reopen it with the schedule it was recorded with",
+                    file, appointment.id, appointment.patient_name
+                ));
+            }
+        }
+        if encounter.appointment_id == 0 {
+            encounter.appointment_id = appointment
+                .map(|appointment| appointment.id)
+                .ok_or_else(|| format!("{} does not name its appointment and {} has none scheduled", file, encounter.patient_name))?;
+        }
+        if encounter.patient_id.is_none() {
+            encounter.patient_id = appointment.and_then(|appointment| appointment.patient_id.clone());
+        }
+        
+        let number = encounter.appointment_id;
+        if self.open_encounters.contains_key(&number) {
+            return Err(format!(
+                "Appointment {} already has an open encounter; commit or discard it first",
+                number
+            ));
+        }
+        // Opening the file would replace the draft
+        if self.drafts.load(number)?.is_some() {
+            return Err(format!(
+                "Appointment {} has an unfinished draft; resume it with 'resume encounter' and commit or discard it first",
+                number
+            ));
+        }
+        let original = record.encounter()?;
+        encounter.amends = Some(Amendment {
+            encounter_id: id,
+            version: latest + 1,
+            opened_from: file.to_string(),
+            period_start: original.period.start.clone(),
+            period_end: original.period.end.clone(),
+            length_minutes: original.length.as_ref().map(|length| length.value.round() as i64),
+            resource_id: record.encounter_resource_id().unwrap_or_default(),
+            exported: record.exported(),
+        });
+        self.open_encounters.insert(number, encounter);
+        self.set_current(number)?;
+        Ok(&self.open_encounters[&number])
+    }
+    
+    pub fn switch_to(&mut self, number: usize) -> Result<&Encounter, String> {
+        if !self.open_encounters.contains_key(&number) {
+            return Err(format!("No open encounter for appointment {}", number));
+        }
+        self.set_current(number)?;
+        Ok(&self.open_encounters[&number])
+    }
+    
+    pub fn current_encounter(&self) -> Result<&Encounter, String> {
+        self.current
+            .and_then(|number| self.open_encounters.get(&number))
+            .ok_or_else(|| "No active encounter".to_string())
+    }
+    
+    fn current_mut(&mut self) -> Result<&mut Encounter, String> {
+        self.current
+            .and_then(|number| self.open_encounters.get_mut(&number))
+            .ok_or_else(|| "No active encounter".to_string())
+    }
+    
+    // Apply an edit to the current encounter, recording it for undo. A failed
+    // edit leaves both the encounter and its history untouched.
+    fn edit<T>(
+        &mut self,
+        description: String,
+        apply: impl FnOnce(&mut Encounter) -> Result<T, String>,
+    ) -> Result<T, String> {
+        let number = self.current.ok_or_else(|| "No active encounter".to_string())?;
+        let encounter = self.current_mut()?;
+        let before = encounter.clone();
+        let result = apply(encounter)?;
+        
+        let history = self.history.entry(number).or_default();
+        history.undo.push((description, before));
+        history.redo.clear();
+        self.save_draft()?;
+        Ok(result)
+    }
+    
+    pub fn add_section(&mut self, section: String, entry: SectionEntry) -> Result<usize, String> {
+        let description = format!("register {} \"{}\"", section, entry.text);
+        self.edit(description, |encounter| Ok(encounter.add_section(section, entry)))
+    }
+    
+    pub fn amend_section(&mut self, section: &str, entry: SectionEntry) -> Result<SectionEntry, String> {
+        let description = format!("amend {} \"{}\"", section, entry.text);
+        self.edit(description, |encounter| encounter.amend_section(section, entry))
+    }
+    
+    pub fn replace_section(&mut self, section: String, entry: SectionEntry) -> Result<usize, String> {
+        let description = format!("replace {} \"{}\"", section, entry.text);
+        self.edit(description, |encounter| Ok(encounter.replace_section(section, entry)))
+    }
+    
+    pub fn rank_diagnosis(&mut self, number: usize, to: Option<usize>) -> Result<(SectionEntry, usize), String> {
+        let description = match to {
+            Some(1) => format!("rank diagnosis {} first", number),
+            Some(rank) => format!("rank diagnosis {} {}", number, rank),
+            None => format!("rank diagnosis {} last", number),
+        };
+        self.edit(description, |encounter| encounter.rank_diagnosis(number, to))
+    }
+    
+    pub fn mark_diagnosis(&mut self, number: usize, status: DiagnosisStatus) -> Result<SectionEntry, String> {
+        let description = format!("mark diagnosis {} {}", number, status);
+        self.edit(description, |encounter| encounter.mark_diagnosis(number, status))
+    }
+    
+    pub fn clear_section(&mut self, section: &str) -> Result<usize, String> {
+        let description = format!("clear {}", section);
+        self.edit(description, |encounter| encounter.clear_section(section))
+    }
+    
+    pub fn add_action(&mut self, action: ClinicalAction) -> Result<(), String> {
+        let description = action.to_string();
+        self.edit(description, |encounter| {
+            encounter.add_action(action);
+            Ok(())
+        })
+    }
+    
+    // Add a template's section text and orders to the current encounter as a
+    // single edit, so one undo takes the whole template back out
+    pub fn apply_template(
+        &mut self,
+        name: &str,
+        entries: Vec<(String, SectionEntry)>,
+        orders: Vec<ClinicalAction>,
+        interactions: Vec<Interaction>,
+    ) -> Result<(), String> {
+        let description = format!("apply template {}", name);
+        self.edit(description, |encounter| {
+            for (section, entry) in entries {
+                encounter.add_section(section, entry);
+            }
+            for order in orders {
+                encounter.add_action(order);
+            }
+            encounter.interactions.extend(interactions);
+            Ok(())
+        })
+    }
+    
+    pub fn fill_placeholder(&mut self, placeholder: &str, value: &str) -> Result<usize, String> {
+        let description = format!("fill \"{}\" \"{}\"", placeholder, value);
+        self.edit(description, |encounter| match encounter.fill_placeholder(placeholder, value) {
+            0 => Err(format!("No {{{{{}}}}} placeholder in the current encounter", placeholder)),
+            filled => Ok(filled),
+        })
+    }
+    
+    // Add a prescription together with the interactions found for it, so
+    // undoing the prescription drops its warnings too
+    pub fn prescribe(&mut self, prescription: Prescription, interactions: Vec<Interaction>) -> Result<(), String> {
+        let action = ClinicalAction::Prescription(prescription);
+        let description = action.to_string();
+        self.edit(description, |encounter| {
+            encounter.add_action(action);
+            encounter.interactions.extend(interactions);
+            Ok(())
+        })
+    }
+    
+    // Revert the last edit of the current encounter; returns its description
+    pub fn undo(&mut self) -> Result<String, String> {
+        self.step_history(true)
+    }
+    
+    // Reapply the last undone edit of the current encounter
+    pub fn redo(&mut self) -> Result<String, String> {
+        self.step_history(false)
+    }
+    
+    fn step_history(&mut self, undo: bool) -> Result<String, String> {
+        let number = self.current.ok_or_else(|| "No active encounter".to_string())?;
+        let history = self.history.entry(number).or_default();
+        let (from, to) = if undo {
+            (&mut history.undo, &mut history.redo)
+        } else {
+            (&mut history.redo, &mut history.undo)
+        };
+        let (description, mut snapshot) = match from.pop() {
+            Some(step) => step,
+            None => {
+                let patient = &self.open_encounters[&number].patient_name;
+                let verb = if undo { "undo" } else { "redo" };
+                return Err(format!("Nothing to {} for {}", verb, patient));
+            }
+        };
+        
+        // Snapshots restore what was recorded, not when: the timing fields keep
+        // their current values, so pauses taken since the edit are not lost
+        let encounter = self.open_encounters.get_mut(&number).expect("current encounter is open");
+        snapshot.started_at = encounter.started_at.clone();
+        snapshot.ended_at = encounter.ended_at.clone();
+        snapshot.pauses = encounter.pauses.clone();
+        let replaced = std::mem::replace(encounter, snapshot);
+        to.push((description.clone(), replaced));
+        self.save_draft()?;
+        Ok(description)
+    }
+    
+    // Drop the current encounter without saving it. Encounters with registered
+    // content are only dropped when `confirmed` is set.
+    pub fn discard_encounter(&mut self, confirmed: bool) -> Result<Encounter, String> {
+        let encounter = self.current_mut()?;
+        if !confirmed && !encounter.is_empty() {
+            return Err(format!(
+                "The encounter for {} has uncommitted entries; use 'discard encounter --confirm' to abandon it",
+                encounter.patient_name
+            ));
         }
+        let number = self.current.take().expect("current encounter checked above");
+        let encounter = self.open_encounters.remove(&number).expect("current encounter is open");
+        self.history.remove(&number);
+        self.drafts.remove(number)?;
+        Ok(encounter)
+    }
+    
+    // Validate and save the current encounter. Errors always block the commit;
+    // warnings block it unless `force` is set, in which case they are recorded
+    // in the saved encounter. Returns the file written and the overridden warnings.
+    pub fn commit_encounter(&mut self, force: bool, patient: &Patient) -> Result<(String, Vec<String>), String> {
+        let number = match self.current {
+            Some(number) => number,
+            None => return Err("No active encounter to commit".to_string()),
+        };
+        let encounter = self
+            .open_encounters
+            .get(&number)
+            .ok_or_else(|| "No active encounter to commit".to_string())?;
+        
+        let findings = validation::check(&self.rules, encounter);
+        let blocked = findings.iter().any(|finding| finding.severity == Severity::Error);
+        if blocked || (!findings.is_empty() && !force) {
+            let hint = if blocked {
+                "fix the errors before committing"
+            } else {
+                "fix them or use 'commit encounter --force' to commit anyway"
+            };
+            let details: Vec<String> = findings.iter().map(|finding| finding.to_string()).collect();
+            return Err(format!(
+                "The encounter for {} did not pass validation; {}:\n  {}",
+                encounter.patient_name,
+                hint,
+                details.join("\n  ")
+            ));
+        }
+        
+        // Complete a copy, so the open encounter is left as it was if the
+        // record cannot be written
+        let mut completed = encounter.clone();
+        completed.overridden_warnings = findings.into_iter().map(|finding| finding.message).collect();
+        completed.complete();
+        let encounter = &completed;
+        
+        let render = |id: usize| {
+            let bundle = crate::fhir::create_bundle(id.to_string(), encounter, patient);
+            
+            serde_json::to_string_pretty(&bundle)
+                .map_err(|e| format!("Failed to serialize encounter: {}", e))
+        };
+        let path = match &encounter.amends {
+            // An addendum is written next to the version it amends
+            Some(amendment) => {
+                let json = render(amendment.encounter_id)?;
+                self.store.save_version(amendment.encounter_id, amendment.version, &json)?
+            },
+            // Otherwise the store picks the ID, so render the FHIR record once it is known
+            None => self.store.save_new(render)?.1,
+        };
+        
+        self.open_encounters.remove(&number);
+        self.history.remove(&number);
+        self.current = None;
+        self.drafts.remove(number)?;
+        Ok((path.display().to_string(), completed.overridden_warnings))
     }
 }
@@ -6,27 +6,59 @@ use rustyline::Context;
 use rustyline::Helper;
 use rustyline::validate::{ValidationContext, ValidationResult, Validator};
 use std::borrow::Cow::{self, Borrowed, Owned};
+use crate::allergy;
+use crate::executor;
+use crate::macros;
+use crate::patient;
+use crate::sections;
 
 // Define all available commands for autocompletion
 const COMMANDS: &[&str] = &[
     "fetch appointments",
     "attend appointment",
-    "register complaint",
-    "register physical exam",
-    "register diagnostic hypothesis",
+    "switch to appointment",
+    "list open encounters",
+    "list templates",
+    "discard encounter",
+    "resume encounter",
+    "open encounter",
+    "apply template",
+    "fill",
+    "register",
+    "register allergy",
+    "amend",
+    "replace",
+    "clear",
+    "rank diagnosis",
+    "mark diagnosis",
+    "review encounter",
+    "show",
+    "show patient",
+    "update patient",
+    "history",
+    "history appointment",
     "prescribe",
     "request",
     "referral",
+    "undo",
+    "redo",
     "commit encounter",
+    "define alias",
+    "define macro",
+    "undefine",
+    "macros",
     "exit",
 ];
 
-// Define section types for register command
-const SECTIONS: &[&str] = &[
-    "complaint",
-    "physical exam",
-    "diagnostic hypothesis",
-];
+// Fixed commands followed by the user's aliases and macros
+fn commands() -> Vec<String> {
+    let mut commands: Vec<String> = COMMANDS.iter().map(|cmd| cmd.to_string()).collect();
+    commands.extend(macros::names());
+    commands
+}
+
+// Commands that take a section name
+const SECTION_COMMANDS: &[&str] = &["register ", "amend ", "replace ", "clear ", "show "];
 
 pub struct MedicalDSLHelper {}
 
@@ -50,62 +82,65 @@ impl Completer for MedicalDSLHelper {
         // Basic completion for commands
         if line.is_empty() {
             // If line is empty, suggest all commands
-            let candidates: Vec<Pair> = COMMANDS
-                .iter()
+            let candidates: Vec<Pair> = commands()
+                .into_iter()
                 .map(|cmd| Pair {
-                    display: (*cmd).to_string(),
-                    replacement: (*cmd).to_string(),
+                    display: cmd.clone(),
+                    replacement: cmd,
                 })
                 .collect();
             return Ok((0, candidates));
         }
         
         // Handle different completion scenarios based on line content
-        if line.starts_with("register ") {
-            // If typing a register command, suggest sections
-            let register_cmd = "register ";
-            if line.len() <= register_cmd.len() {
-                // Suggest all sections if we just typed "register "
-                let candidates: Vec<Pair> = SECTIONS
-                    .iter()
-                    .map(|section| {
-                        let cmd = format!("register {}", section);
-                        Pair {
-                            display: cmd.clone(),
-                            replacement: cmd,
-                        }
-                    })
-                    .collect();
-                return Ok((register_cmd.len(), candidates));
-            } else {
-                // Try to match partial section name
-                let section_start = line.trim_start_matches(register_cmd);
-                let candidates: Vec<Pair> = SECTIONS
-                    .iter()
-                    .filter(|section| section.starts_with(section_start))
-                    .map(|section| {
-                        let cmd = format!("register {}", section);
-                        Pair {
-                            display: cmd.clone(),
-                            replacement: cmd,
-                        }
-                    })
-                    .collect();
-                if !candidates.is_empty() {
-                    return Ok((register_cmd.len(), candidates));
-                }
+        if let Some(section_cmd) = SECTION_COMMANDS.iter().find(|cmd| line.starts_with(**cmd)) {
+            // If typing a section command, suggest sections matching what was typed
+            let section_start = &line[section_cmd.len()..];
+            let mut names = sections::names();
+            if *section_cmd == "register " {
+                names.push("allergy".to_string());
+            } else if *section_cmd == "show " {
+                names.push("patient".to_string());
             }
+            let candidates: Vec<Pair> = names
+                .iter()
+                .filter(|section| section.starts_with(section_start))
+                .map(|section| {
+                    let cmd = format!("{}{}", section_cmd, section);
+                    Pair {
+                        display: cmd.clone(),
+                        replacement: cmd,
+                    }
+                })
+                .collect();
+            if !candidates.is_empty() {
+                return Ok((0, candidates));
+            }
+        } else if let Some(name_start) = line.strip_prefix("apply template ") {
+            // Template names come from the templates directory
+            let candidates: Vec<Pair> = executor::template_names()
+                .iter()
+                .filter(|name| name.starts_with(name_start))
+                .map(|name| {
+                    let cmd = format!("apply template {}", name);
+                    Pair {
+                        display: cmd.clone(),
+                        replacement: cmd,
+                    }
+                })
+                .collect();
+            return Ok((0, candidates));
         } else if line.starts_with("attend appointment ") {
             // We don't provide completion for appointment numbers as they're dynamic
             return Ok((0, vec![]));
         } else {
             // General command completion
-            let candidates: Vec<Pair> = COMMANDS
-                .iter()
+            let candidates: Vec<Pair> = commands()
+                .into_iter()
                 .filter(|cmd| cmd.starts_with(&line))
                 .map(|cmd| Pair {
-                    display: (*cmd).to_string(),
-                    replacement: (*cmd).to_string(),
+                    display: cmd.clone(),
+                    replacement: cmd,
                 })
                 .collect();
             
@@ -129,7 +164,19 @@ impl Hinter for MedicalDSLHelper {
         } else if line == "commit" {
             Some(" encounter".to_owned())
         } else if line == "register " {
-            Some("complaint|physical exam|diagnostic hypothesis".to_owned())
+            Some(format!("{}|allergy", sections::names().join("|")))
+        } else if SECTION_COMMANDS.contains(&line) {
+            Some(sections::names().join("|"))
+        } else if line == "register allergy " {
+            Some(format!("\"<substance>\" [severity {}]", allergy::CRITICALITIES.join("|")))
+        } else if line == "apply " {
+            Some("template <name>".to_owned())
+        } else if line == "define " {
+            Some("alias <name> = <command> | macro <name> = <command> ; <command>".to_owned())
+        } else if line == "fill " {
+            Some("<placeholder> \"<text>\"".to_owned())
+        } else if line == "update patient " {
+            Some(format!("{} <value>", patient::FIELDS.join("|")))
         } else if line == "attend " {
             Some("appointment <number>".to_owned())
         } else {
@@ -0,0 +1,250 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+// A clinical decision taken during an encounter
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ClinicalAction {
+    Prescription(Prescription),
+    ExamRequest(ExamRequest),
+    Referral(Referral),
+}
+
+impl ClinicalAction {
+    // Resource the order was exported as, for an order read back from a
+    // committed encounter
+    pub fn exported(&self) -> Option<&Exported> {
+        match self {
+            ClinicalAction::Prescription(prescription) => prescription.exported.as_ref(),
+            ClinicalAction::ExamRequest(exam) => exam.exported.as_ref(),
+            ClinicalAction::Referral(referral) => referral.exported.as_ref(),
+        }
+    }
+}
+
+// The resource something was exported as in a committed encounter. A reopened
+// encounter keeps it, so its addendum updates that resource instead of
+// creating another, and keeps the original time.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Exported {
+    pub id: String,
+    // authoredOn of an order, identifiedDateTime of an interaction
+    pub at: String,
+}
+
+// A medication order. Orders written with the prescribe grammar have every
+// field filled in; free-text orders only carry the text in `drug`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Prescription {
+    pub drug: String,
+    pub strength: Option<f64>,
+    pub unit: Option<String>,
+    pub route: Option<String>,
+    pub frequency: Option<String>,
+    pub duration: Option<TreatmentDuration>,
+    pub quantity: Option<u32>,
+    pub refills: Option<u32>,
+    pub structured: bool,
+    // Why the drug is prescribed despite a recorded allergy it matches
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allergy_override: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exported: Option<Exported>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TreatmentDuration {
+    pub value: u32,
+    pub unit: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExamRequest {
+    pub exam: String,
+    pub code: Option<String>,
+    pub priority: Priority,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exported: Option<Exported>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Referral {
+    pub specialty: String,
+    pub reason: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exported: Option<Exported>,
+}
+
+// Request priority, using the FHIR request-priority codes
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Priority {
+    Routine,
+    Urgent,
+    Asap,
+    Stat,
+}
+
+impl Priority {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "routine" => Some(Priority::Routine),
+            "urgent" => Some(Priority::Urgent),
+            "asap" => Some(Priority::Asap),
+            "stat" => Some(Priority::Stat),
+            _ => None,
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            Priority::Routine => "routine",
+            Priority::Urgent => "urgent",
+            Priority::Asap => "asap",
+            Priority::Stat => "stat",
+        }
+    }
+}
+
+// Verification status of a diagnostic hypothesis
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum DiagnosisStatus {
+    Provisional,
+    Confirmed,
+    RuledOut,
+}
+
+impl DiagnosisStatus {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "provisional" => Some(DiagnosisStatus::Provisional),
+            "confirmed" => Some(DiagnosisStatus::Confirmed),
+            "ruled-out" => Some(DiagnosisStatus::RuledOut),
+            _ => None,
+        }
+    }
+
+    // Status for a FHIR condition-ver-status code
+    pub fn from_code(code: &str) -> Option<Self> {
+        match code {
+            "provisional" => Some(DiagnosisStatus::Provisional),
+            "confirmed" => Some(DiagnosisStatus::Confirmed),
+            "refuted" => Some(DiagnosisStatus::RuledOut),
+            _ => None,
+        }
+    }
+
+    // FHIR condition-ver-status code
+    pub fn code(&self) -> &'static str {
+        match self {
+            DiagnosisStatus::Provisional => "provisional",
+            DiagnosisStatus::Confirmed => "confirmed",
+            DiagnosisStatus::RuledOut => "refuted",
+        }
+    }
+}
+
+impl fmt::Display for DiagnosisStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DiagnosisStatus::Provisional => write!(f, "provisional"),
+            DiagnosisStatus::Confirmed => write!(f, "confirmed"),
+            DiagnosisStatus::RuledOut => write!(f, "ruled-out"),
+        }
+    }
+}
+
+impl Prescription {
+    pub fn free_text(text: String) -> Self {
+        Self {
+            drug: text,
+            strength: None,
+            unit: None,
+            route: None,
+            frequency: None,
+            duration: None,
+            quantity: None,
+            refills: None,
+            structured: false,
+            allergy_override: None,
+            exported: None,
+        }
+    }
+
+    // Dose amount and unit, e.g. "500 mg"
+    pub fn dose_text(&self) -> Option<String> {
+        match (self.strength, &self.unit) {
+            (Some(strength), Some(unit)) => Some(format!("{} {}", strength, unit)),
+            _ => None,
+        }
+    }
+
+    // Dosage instructions without the drug name, e.g. "500 mg po q8h for 7 days"
+    pub fn dosage_text(&self) -> String {
+        let mut parts: Vec<String> = self.dose_text().into_iter().collect();
+        parts.extend(self.route.iter().cloned());
+        parts.extend(self.frequency.iter().cloned());
+        if let Some(duration) = &self.duration {
+            parts.push(format!("for {}", duration));
+        }
+        parts.join(" ")
+    }
+}
+
+impl fmt::Display for TreatmentDuration {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.value, self.unit)
+    }
+}
+
+impl fmt::Display for Prescription {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let dosage = self.dosage_text();
+        if dosage.is_empty() {
+            write!(f, "{}", self.drug)?;
+        } else {
+            write!(f, "{} {}", self.drug, dosage)?;
+        }
+        if let Some(quantity) = self.quantity {
+            write!(f, " #{}", quantity)?;
+        }
+        if let Some(refills) = self.refills {
+            write!(f, " refills {}", refills)?;
+        }
+        if let Some(reason) = &self.allergy_override {
+            write!(f, " (allergy override: {})", reason)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for ExamRequest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.exam)?;
+        if let Some(code) = &self.code {
+            write!(f, " [{}]", code)?;
+        }
+        if self.priority != Priority::Routine {
+            write!(f, " ({})", self.priority.code())?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Referral {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.specialty)?;
+        if let Some(reason) = &self.reason {
+            write!(f, " for {}", reason)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for ClinicalAction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ClinicalAction::Prescription(prescription) => write!(f, "Prescription: {}", prescription),
+            ClinicalAction::ExamRequest(exam) => write!(f, "Exam Request: {}", exam),
+            ClinicalAction::Referral(referral) => write!(f, "Referral: {}", referral),
+        }
+    }
+}
@@ -0,0 +1,30 @@
+use chrono::{DateTime, FixedOffset, Local};
+use std::sync::Mutex;
+
+// Environment variable that pins the clock to an RFC 3339 time, so the same
+// commands always produce byte-for-byte identical encounter files
+pub const FIXED_TIME_VAR: &str = "CHARCOT_FIXED_TIME";
+
+lazy_static::lazy_static! {
+    static ref FIXED_TIME: Mutex<Option<DateTime<FixedOffset>>> = Mutex::new(None);
+}
+
+// Read FIXED_TIME_VAR, if set, and pin the clock to it
+pub fn init_from_env() -> Result<(), String> {
+    let value = match std::env::var(FIXED_TIME_VAR) {
+        Ok(value) => value,
+        Err(_) => return Ok(()),
+    };
+    let time = DateTime::parse_from_rfc3339(value.trim())
+        .map_err(|e| format!("Invalid {} \"{}\" (expected an RFC 3339 time): {}", FIXED_TIME_VAR, value, e))?;
+    *FIXED_TIME.lock().unwrap() = Some(time);
+    Ok(())
+}
+
+// Current time, or the pinned time when the clock is fixed
+pub fn now() -> DateTime<FixedOffset> {
+    match *FIXED_TIME.lock().unwrap() {
+        Some(time) => time,
+        None => Local::now().fixed_offset(),
+    }
+}
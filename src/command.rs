@@ -1,11 +1,55 @@
+use crate::allergy::Criticality;
+use crate::clinical::{DiagnosisStatus, ExamRequest, Prescription, Referral};
+
 #[derive(Debug)]
 pub enum Command {
     FetchAppointments,
     AttendAppointment(usize),
+    SwitchEncounter(usize),
+    ListOpenEncounters,
+    DiscardEncounter { confirmed: bool },
+    ResumeEncounter,
+    // Reopen a committed encounter file to add an addendum
+    OpenEncounter { file: String },
+    // Add a visit template's section text and orders to the current encounter
+    ApplyTemplate { name: String },
+    ListTemplates,
+    // Replace a template {{placeholder}} with the physician's text
+    FillPlaceholder { placeholder: String, value: String },
     RegisterSection { section: String, details: String },
-    Prescribe(String),
-    Request(String),
-    Referral(String),
-    CommitEncounter,
-    Unknown(String),
+    // Record an allergy of the current patient in the registry
+    RegisterAllergy { substance: String, criticality: Criticality },
+    AmendSection { section: String, details: String },
+    ReplaceSection { section: String, details: String },
+    ClearSection { section: String },
+    // Move a diagnosis to a new rank; `to: None` moves it last
+    RankDiagnosis { number: usize, to: Option<usize> },
+    MarkDiagnosis { number: usize, status: DiagnosisStatus },
+    ShowPatient,
+    UpdatePatient { field: String, value: String },
+    ReviewEncounter,
+    ShowSection { section: String },
+    History(HistoryTarget),
+    Undo,
+    Redo,
+    Prescribe(Prescription),
+    Request(ExamRequest),
+    Referral(Referral),
+    CommitEncounter { force: bool },
+    // Name the start of a command, e.g. rx for prescribe
+    DefineAlias { name: String, expansion: String },
+    // Name a sequence of commands
+    DefineMacro { name: String, commands: Vec<String> },
+    Undefine { name: String },
+    ListMacros,
+}
+
+// Whose committed encounters `history` summarises
+#[derive(Debug)]
+pub enum HistoryTarget {
+    // The patient of the current encounter
+    Current,
+    Appointment(usize),
+    // A medical record number or patient name
+    Patient(String),
 }
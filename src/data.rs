@@ -0,0 +1,14 @@
+// Reference tables from data/, bundled with the binary so lookups work
+// offline
+pub const ICD10: &str = include_str!("../data/icd10.tsv");
+pub const INTERACTIONS: &str = include_str!("../data/interactions.tsv");
+pub const DRUG_CLASSES: &str = include_str!("../data/drug_classes.tsv");
+
+// Rows of a bundled table: tab-separated fields, trimmed. Blank lines and
+// lines starting with # are skipped.
+pub fn rows(table: &'static str) -> impl Iterator<Item = Vec<&'static str>> {
+    table
+        .lines()
+        .filter(|line| !line.trim().is_empty() && !line.starts_with('#'))
+        .map(|line| line.split('\t').map(str::trim).collect())
+}
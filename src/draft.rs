@@ -0,0 +1,115 @@
+use crate::appointment::Encounter;
+use crate::clock;
+use crate::store;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+// On-disk form of an in-progress encounter
+#[derive(Serialize, Deserialize)]
+pub struct Draft {
+    pub appointment_number: usize,
+    pub encounter: Encounter,
+    // When the draft was written
+    #[serde(default)]
+    pub saved_at: String,
+}
+
+// Autosave area for encounters that have not been committed yet. The full
+// encounter is rewritten after every change, so a crash or Ctrl-C loses at
+// most the command being typed.
+pub struct DraftStore {
+    dir: PathBuf,
+}
+
+impl DraftStore {
+    // Drafts live in a hidden directory next to the committed encounters
+    pub fn new(output_dir: &Path) -> Self {
+        Self {
+            dir: output_dir.join(".drafts"),
+        }
+    }
+
+    fn path(&self, appointment_number: usize) -> PathBuf {
+        self.dir.join(format!("appointment_{}.json", appointment_number))
+    }
+
+    // Replace the draft for an appointment
+    pub fn save(&self, appointment_number: usize, encounter: &Encounter) -> Result<(), String> {
+        fs::create_dir_all(&self.dir)
+            .map_err(|e| format!("Failed to create {}: {}", self.dir.display(), e))?;
+
+        let draft = DraftRef {
+            appointment_number,
+            encounter,
+            saved_at: clock::now().to_rfc3339(),
+        };
+        let json = serde_json::to_string_pretty(&draft)
+            .map_err(|e| format!("Failed to serialize draft: {}", e))?;
+
+        let path = self.path(appointment_number);
+        store::replace_atomic(&path, &json)
+            .map_err(|e| format!("Failed to save draft {}: {}", path.display(), e))
+    }
+
+    pub fn remove(&self, appointment_number: usize) -> Result<(), String> {
+        let path = self.path(appointment_number);
+        match fs::remove_file(&path) {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(format!("Failed to remove draft {}: {}", path.display(), e)),
+        }
+    }
+
+    // The draft for an appointment, if one was left behind
+    pub fn load(&self, appointment_number: usize) -> Result<Option<Draft>, String> {
+        let path = self.path(appointment_number);
+        if !path.exists() {
+            return Ok(None);
+        }
+        read(&path).map(Some)
+    }
+
+    // Every draft left behind by an earlier session, in appointment order.
+    // A draft that cannot be read is left on disk and reported instead, so it
+    // does not keep the others from being resumed.
+    pub fn load_all(&self) -> Result<(Vec<Draft>, Vec<String>), String> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok((Vec::new(), Vec::new())),
+            Err(e) => return Err(format!("Failed to read {}: {}", self.dir.display(), e)),
+        };
+
+        let mut drafts = Vec::new();
+        let mut skipped = Vec::new();
+        for entry in entries.filter_map(|entry| entry.ok()) {
+            let path = entry.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some("json") {
+                continue;
+            }
+            match read(&path) {
+                Ok(draft) => drafts.push(draft),
+                Err(e) => skipped.push(format!("{}; skipped", e)),
+            }
+        }
+
+        drafts.sort_by_key(|draft| draft.appointment_number);
+        skipped.sort();
+        Ok((drafts, skipped))
+    }
+}
+
+fn read(path: &Path) -> Result<Draft, String> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read draft {}: {}", path.display(), e))?;
+    serde_json::from_str(&content).map_err(|e| format!("Corrupt draft {}: {}", path.display(), e))
+}
+
+// Borrowed counterpart of Draft, so saving doesn't need to clone the encounter
+#[derive(Serialize)]
+struct DraftRef<'a> {
+    appointment_number: usize,
+    encounter: &'a Encounter,
+    saved_at: String,
+}
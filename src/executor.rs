@@ -1,99 +1,797 @@
-use crate::command::Command;
+use crate::allergy::{self, Allergy};
+use crate::clinical::{ClinicalAction, Prescription};
+use crate::command::{Command, HistoryTarget};
 use crate::appointment::{AppointmentManager, EncounterManager};
+use crate::appointment::{SectionEntry, Started};
+use crate::draft::DraftStore;
+use crate::history::{self, PatientKey};
+use crate::icd10::{self, Lookup};
+use crate::interaction::{self, Interaction, Medication};
+use crate::macros::{self, Definition};
+use crate::patient::{Patient, PatientRegistry};
+use crate::review;
+use crate::sections;
+use crate::store::EncounterStore;
+use crate::template;
+use crate::validation::Rule;
+use std::path::{Path, PathBuf};
 use std::sync::Mutex;
 
 // Create singleton managers
 lazy_static::lazy_static! {
-    static ref APPOINTMENT_MANAGER: AppointmentManager = AppointmentManager::new();
-    static ref ENCOUNTER_MANAGER: Mutex<EncounterManager> = Mutex::new(EncounterManager::new());
+    static ref APPOINTMENT_MANAGER: Mutex<AppointmentManager> = Mutex::new(AppointmentManager::new(Vec::new()));
+    static ref ENCOUNTER_MANAGER: Mutex<EncounterManager> = Mutex::new(EncounterManager::new(
+        EncounterStore::new(PathBuf::from(".")),
+        DraftStore::new(Path::new(".")),
+    ));
+    static ref PATIENT_REGISTRY: Mutex<PatientRegistry> = Mutex::new(PatientRegistry::new(Path::new(".")));
+    static ref TEMPLATE_DIR: Mutex<PathBuf> = Mutex::new(PathBuf::from("templates"));
 }
 
-pub fn execute(command: Command) -> String {
+// Replace the schedule used by `fetch appointments` and `attend appointment`
+pub fn set_appointments(manager: AppointmentManager) {
+    *APPOINTMENT_MANAGER.lock().unwrap() = manager;
+}
+
+// Replace the registry patients are looked up in
+pub fn set_patients(registry: PatientRegistry) {
+    *PATIENT_REGISTRY.lock().unwrap() = registry;
+}
+
+// Number of encounters started but not yet committed
+pub fn open_encounter_count() -> usize {
+    ENCOUNTER_MANAGER.lock().unwrap().open_encounters.len()
+}
+
+// Change the directory committed encounters and drafts are written to
+pub fn set_output_dir(dir: PathBuf) {
+    let mut encounter_manager = ENCOUNTER_MANAGER.lock().unwrap();
+    encounter_manager.drafts = DraftStore::new(&dir);
+    encounter_manager.store = EncounterStore::new(dir);
+}
+
+// Change the directory `apply template` reads templates from
+pub fn set_template_dir(dir: PathBuf) {
+    *TEMPLATE_DIR.lock().unwrap() = dir;
+}
+
+// Names of the templates `apply template` can use
+pub fn template_names() -> Vec<String> {
+    template::names(&TEMPLATE_DIR.lock().unwrap()).unwrap_or_default()
+}
+
+// Replace the rules checked by `commit encounter`
+pub fn set_validation_rules(rules: Vec<Rule>) {
+    ENCOUNTER_MANAGER.lock().unwrap().rules = rules;
+}
+
+// Describe the drafts left behind by an earlier session that are not open yet,
+// and the drafts that could not be read
+pub fn pending_drafts() -> Result<(Vec<String>, Vec<String>), String> {
+    let encounter_manager = ENCOUNTER_MANAGER.lock().unwrap();
+    let (drafts, skipped) = encounter_manager.drafts.load_all()?;
+    let pending = drafts
+        .into_iter()
+        .filter(|draft| !encounter_manager.open_encounters.contains_key(&draft.appointment_number))
+        .map(|draft| {
+            format!(
+                "Appointment {}: {} - {} ({} section(s), {} action(s))",
+                draft.appointment_number,
+                draft.encounter.patient_name,
+                draft.encounter.reason,
+                draft.encounter.sections.len(),
+                draft.encounter.actions.len()
+            )
+        })
+        .collect();
+    Ok((pending, skipped))
+}
+
+// Registry record of the current encounter's patient, as last saved by any
+// session
+fn current_patient(encounter_manager: &EncounterManager) -> Result<Patient, String> {
+    let encounter = encounter_manager.current_encounter()?;
+    let mut registry = PATIENT_REGISTRY.lock().unwrap();
+    registry.reload()?;
+    encounter
+        .patient_id
+        .as_deref()
+        .and_then(|id| registry.get(id))
+        .cloned()
+        .ok_or_else(|| {
+            format!(
+                "{} is not linked to a registered patient; give the appointment a patient_id in the schedule",
+                encounter.patient_name
+            )
+        })
+}
+
+// Drugs a new prescription could interact with: the current encounter's
+// prescriptions, and the patient's active medications from earlier committed
+// encounters when those can be read
+fn medications_in_use(encounter_manager: &EncounterManager) -> Result<Vec<Medication>, String> {
+    let encounter = encounter_manager.current_encounter()?;
+    let mut medications: Vec<Medication> = encounter
+        .prescribed_drugs()
+        .into_iter()
+        .map(|drug| Medication { drug, source: None })
+        .collect();
+    
+    let patient = PatientKey {
+        id: encounter.patient_id.clone(),
+        name: encounter.patient_name.clone(),
+    };
+    // The file an addendum amends is already part of the encounter
+    let amended = encounter.amends.as_ref().map(|amendment| amendment.opened_from.as_str());
+    if let Ok((visits, _)) = history::lookup(&encounter_manager.store, &patient) {
+        for visit in visits.iter().filter(|visit| Some(visit.file.as_str()) != amended) {
+            medications.extend(visit.active_medications.iter().map(|drug| Medication {
+                drug: drug.clone(),
+                source: Some(visit.file.clone()),
+            }));
+        }
+    }
+    Ok(medications)
+}
+
+// Append a warning line for each interaction to a command's output
+fn with_interactions(mut output: String, interactions: &[Interaction]) -> String {
+    for interaction in interactions {
+        output.push_str(&format!("\nWarning: {}", interaction));
+    }
+    output
+}
+
+// Allergies recorded for the current encounter's patient, including those
+// recorded in other sessions. A patient missing from the registry has none.
+fn current_allergies(encounter_manager: &EncounterManager) -> Result<Vec<Allergy>, String> {
+    let encounter = encounter_manager.current_encounter()?;
+    let mut registry = PATIENT_REGISTRY.lock().unwrap();
+    registry.reload()?;
+    Ok(encounter
+        .patient_id
+        .as_deref()
+        .and_then(|id| registry.get(id))
+        .map(|patient| patient.allergies.clone())
+        .unwrap_or_default())
+}
+
+// Check a prescription against the patient's allergies. A match with a low
+// criticality allergy, or one the prescription overrides, is returned as a
+// warning; any other match blocks the prescription.
+fn allergy_warnings(prescription: &Prescription, allergies: &[Allergy]) -> Result<Vec<String>, String> {
+    let conflicts = allergy::conflicts(&prescription.drug, allergies);
+    if let Some(reason) = &prescription.allergy_override {
+        if conflicts.is_empty() {
+            return Err(format!(
+                "{} matches no recorded allergy; remove override \"{}\"",
+                prescription.drug, reason
+            ));
+        }
+        return Ok(conflicts
+            .iter()
+            .map(|conflict| format!("{}, overridden: {}", conflict, reason))
+            .collect());
+    }
+    if let Some(conflict) = conflicts.iter().find(|conflict| conflict.blocks()) {
+        return Err(format!(
+            "{} not prescribed: {}; to prescribe it anyway, add override \"<reason>\"",
+            prescription.drug, conflict
+        ));
+    }
+    Ok(conflicts.iter().map(|conflict| conflict.to_string()).collect())
+}
+
+// Patient whose history was asked for. A name nobody in the registry has is
+// still looked up, since encounters committed before the registry existed
+// only carry the patient's name.
+fn history_patient(target: HistoryTarget) -> Result<PatientKey, String> {
+    match target {
+        HistoryTarget::Current => {
+            let encounter_manager = ENCOUNTER_MANAGER.lock().unwrap();
+            let encounter = encounter_manager.current_encounter()?;
+            Ok(PatientKey {
+                id: encounter.patient_id.clone(),
+                name: encounter.patient_name.clone(),
+            })
+        },
+        HistoryTarget::Appointment(number) => {
+            let appointment_manager = APPOINTMENT_MANAGER.lock().unwrap();
+            let appointment = appointment_manager
+                .get_appointment(number)
+                .ok_or_else(|| format!("Invalid appointment number {}", number))?;
+            Ok(PatientKey {
+                id: appointment.patient_id.clone(),
+                name: appointment.patient_name.clone(),
+            })
+        },
+        HistoryTarget::Patient(query) => {
+            let mut registry = PATIENT_REGISTRY.lock().unwrap();
+            // Another session may have registered the patient since startup
+            registry.reload()?;
+            if let Some(patient) = registry.get(&query) {
+                return Ok(PatientKey {
+                    id: Some(patient.id.clone()),
+                    name: patient.name.clone(),
+                });
+            }
+            match registry.find_by_name(&query).as_slice() {
+                [] => Ok(PatientKey { id: None, name: query }),
+                [patient] => Ok(PatientKey {
+                    id: Some(patient.id.clone()),
+                    name: patient.name.clone(),
+                }),
+                patients => {
+                    let ids: Vec<&str> = patients.iter().map(|patient| patient.id.as_str()).collect();
+                    Err(format!(
+                        "Several patients are named {}; use a medical record number ({})",
+                        query,
+                        ids.join(", ")
+                    ))
+                },
+            }
+        },
+    }
+}
+
+// Human-readable summary of a patient record
+fn describe_patient(patient: &Patient) -> String {
+    let mut output = format!("{} ({})\n", patient.name, patient.id);
+    output.push_str(&format!(
+        "  Birth date: {}\n  Sex: {}\n",
+        patient.birth_date.as_deref().unwrap_or("unknown"),
+        patient.sex.as_deref().unwrap_or("unknown")
+    ));
+    for contact in &patient.contacts {
+        output.push_str(&format!("  {}: {}\n", contact.system, contact.value));
+    }
+    if patient.allergies.is_empty() {
+        output.push_str("  Allergies: none recorded\n");
+    } else {
+        let allergies: Vec<String> = patient
+            .allergies
+            .iter()
+            .map(|allergy| format!("{} ({} criticality)", allergy.substance, allergy.criticality))
+            .collect();
+        output.push_str(&format!("  Allergies: {}\n", allergies.join(", ")));
+    }
+    output
+}
+
+// Build the entry for a section, coding diagnostic hypotheses with ICD-10.
+// Also returns a line describing the coding outcome for the physician to check.
+fn section_entry(section: &str, details: String) -> (SectionEntry, Option<String>) {
+    let mut entry = SectionEntry::new(details);
+    if sections::diagnosis_section().as_deref() != Some(section) {
+        return (entry, None);
+    }
+    
+    let note = match icd10::lookup(&entry.text) {
+        Lookup::Found(code) => {
+            let note = format!("ICD-10: {} {}", code.code, code.description);
+            entry.code = Some(code);
+            note
+        },
+        Lookup::Ambiguous(candidates) => {
+            let candidates: Vec<String> = candidates
+                .iter()
+                .map(|candidate| format!("  {} {}", candidate.code, candidate.description))
+                .collect();
+            format!(
+                "Registered uncoded: several ICD-10 codes match; amend with one of:\n{}",
+                candidates.join("\n")
+            )
+        },
+        Lookup::UnknownCode(code) => {
+            format!("Registered uncoded: {} is not in the bundled ICD-10 table", code)
+        },
+        Lookup::NotFound => "Registered uncoded: no ICD-10 match in the bundled table".to_string(),
+    };
+    (entry, Some(note))
+}
+
+// Append the coding note, if any, to a command's output
+fn with_note(output: String, note: Option<String>) -> String {
+    match note {
+        Some(note) => format!("{}\n{}", output, note),
+        None => output,
+    }
+}
+
+// Run a command, returning its output or an error message
+pub fn execute(command: Command) -> Result<String, String> {
     match command {
         Command::FetchAppointments => {
-            let appointments = APPOINTMENT_MANAGER.get_appointments();
+            let appointment_manager = APPOINTMENT_MANAGER.lock().unwrap();
+            let appointments = appointment_manager.get_appointments();
+            if appointments.is_empty() {
+                return Ok("No appointments scheduled".to_string());
+            }
             let mut result = String::new();
             
-            for (i, appointment) in appointments.iter().enumerate() {
+            for appointment in appointments {
+                let patient = match &appointment.patient_id {
+                    Some(id) => format!("{} ({})", appointment.patient_name, id),
+                    None => appointment.patient_name.clone(),
+                };
                 result.push_str(&format!(
                     "{}. Patient: {}, Time: {}, Reason: {}\n",
-                    i + 1,
-                    appointment.patient_name,
+                    appointment.id,
+                    patient,
                     appointment.time,
                     appointment.reason
                 ));
             }
             
-            result
+            Ok(result)
         },
         
         Command::AttendAppointment(number) => {
             // Get patient from appointment manager
-            match APPOINTMENT_MANAGER.get_appointment(number) {
+            let appointment_manager = APPOINTMENT_MANAGER.lock().unwrap();
+            match appointment_manager.get_appointment(number) {
                 Some(appointment) => {
-                    // Start a new encounter
+                    // Start a new encounter, leaving any other open encounters in place
                     let mut encounter_manager = ENCOUNTER_MANAGER.lock().unwrap();
-                    let encounter = encounter_manager.start_encounter(
-                        appointment.id, 
-                        appointment.patient_name.clone(),
-                        appointment.reason.clone()
-                    );
+                    let (encounter, started) = encounter_manager.start_encounter(number, appointment)?;
                     
-                    format!(
+                    match started {
+                        Started::Open => {
+                            return Ok(format!(
+                                "Switched back to the open encounter for {}\nStatus: {}",
+                                encounter.patient_name,
+                                encounter.status
+                            ));
+                        },
+                        Started::Draft => {
+                            return Ok(format!(
+                                "Restored the unfinished draft for {}: {} section(s), {} action(s)\nStatus: {}",
+                                encounter.patient_name,
+                                encounter.sections.len(),
+                                encounter.actions.len(),
+                                encounter.status
+                            ));
+                        },
+                        Started::New => {},
+                    }
+                    let mut output = format!(
                         "Started encounter for {}\nStatus: {}\nEncounter sections initialized.",
                         encounter.patient_name,
                         encounter.status
-                    )
+                    );
+                    let others = encounter_manager.open_encounters.len() - 1;
+                    if others > 0 {
+                        output.push_str(&format!(
+                            "\n{} other encounter(s) still open; use 'list open encounters' to see them.",
+                            others
+                        ));
+                    }
+                    Ok(output)
                 },
-                None => format!("Error: Invalid appointment number {}", number),
+                None => Err(format!("Invalid appointment number {}", number)),
             }
         },
         
-        Command::RegisterSection { section, details } => {
+        Command::SwitchEncounter(number) => {
             let mut encounter_manager = ENCOUNTER_MANAGER.lock().unwrap();
-            match encounter_manager.add_section(section.clone(), details.clone()) {
-                Ok(_) => format!("Added to {} section: \"{}\"", section, details),
-                Err(e) => format!("Error: {}", e),
+            let encounter = encounter_manager.switch_to(number)?;
+            Ok(format!(
+                "Switched to encounter for {} (appointment {})",
+                encounter.patient_name,
+                number
+            ))
+        },
+        
+        Command::ListOpenEncounters => {
+            let encounter_manager = ENCOUNTER_MANAGER.lock().unwrap();
+            if encounter_manager.open_encounters.is_empty() {
+                return Ok("No open encounters".to_string());
+            }
+            
+            let mut result = String::new();
+            for (number, encounter) in &encounter_manager.open_encounters {
+                let marker = if encounter_manager.current == Some(*number) { "*" } else { " " };
+                result.push_str(&format!(
+                    "{} Appointment {}: {} - {} ({} section(s), {} action(s))",
+                    marker,
+                    number,
+                    encounter.patient_name,
+                    encounter.reason,
+                    encounter.sections.len(),
+                    encounter.actions.len()
+                ));
+                if let Some(amendment) = &encounter.amends {
+                    result.push_str(&format!(" - addendum to {}", amendment.opened_from));
+                }
+                result.push('\n');
             }
+            Ok(result)
         },
         
-        Command::Prescribe(medication) => {
+        Command::OpenEncounter { file } => {
+            let appointment_manager = APPOINTMENT_MANAGER.lock().unwrap();
             let mut encounter_manager = ENCOUNTER_MANAGER.lock().unwrap();
-            let action = format!("Prescription: {}", medication);
-            match encounter_manager.add_action(action) {
-                Ok(_) => format!("Prescribed: \"{}\"", medication),
-                Err(e) => format!("Error: {}", e),
+            let encounter = encounter_manager.open_committed(&file, &appointment_manager)?;
+            let amendment = encounter.amends.as_ref().expect("reopened encounters amend a file");
+            Ok(format!(
+                "Reopened {} for {} as appointment {} ({} section(s), {} action(s))\n\
+                 Record the addendum, then 'commit encounter' saves it as {}; {} is kept unchanged.",
+                file,
+                encounter.patient_name,
+                encounter.appointment_id,
+                encounter.sections.len(),
+                encounter.actions.len(),
+                EncounterStore::file_name(amendment.encounter_id, amendment.version),
+                file
+            ))
+        },
+        
+        Command::ResumeEncounter => {
+            let mut encounter_manager = ENCOUNTER_MANAGER.lock().unwrap();
+            let (resumed, skipped) = encounter_manager.resume_drafts()?;
+            let skipped: String = skipped.iter().map(|problem| format!("Warning: {}\n", problem)).collect();
+            if resumed.is_empty() {
+                return Ok(format!("{}No unfinished drafts to resume", skipped));
             }
+            
+            // Drafts saved before patients were registered only carry a name
+            let appointment_manager = APPOINTMENT_MANAGER.lock().unwrap();
+            for number in &resumed {
+                let encounter = encounter_manager.open_encounters.get_mut(number).expect("resumed encounter is open");
+                if encounter.patient_id.is_none() {
+                    encounter.patient_id = appointment_manager
+                        .get_appointment(*number)
+                        .and_then(|appointment| appointment.patient_id.clone());
+                }
+            }
+            
+            let mut result = skipped;
+            for number in resumed {
+                let encounter = &encounter_manager.open_encounters[&number];
+                result.push_str(&format!(
+                    "Restored encounter for {} (appointment {}): {} section(s), {} action(s)\n",
+                    encounter.patient_name,
+                    number,
+                    encounter.sections.len(),
+                    encounter.actions.len()
+                ));
+            }
+            if let Some(number) = encounter_manager.current {
+                let encounter = &encounter_manager.open_encounters[&number];
+                result.push_str(&format!(
+                    "Current encounter: {} (appointment {})",
+                    encounter.patient_name,
+                    number
+                ));
+            }
+            Ok(result)
         },
         
-        Command::Request(exam) => {
+        Command::DiscardEncounter { confirmed } => {
             let mut encounter_manager = ENCOUNTER_MANAGER.lock().unwrap();
-            let action = format!("Exam Request: {}", exam);
-            match encounter_manager.add_action(action) {
-                Ok(_) => format!("Requested: \"{}\"", exam),
-                Err(e) => format!("Error: {}", e),
+            let encounter = encounter_manager.discard_encounter(confirmed)?;
+            Ok(format!("Discarded encounter for {}", encounter.patient_name))
+        },
+        
+        Command::ListTemplates => {
+            let dir = TEMPLATE_DIR.lock().unwrap().clone();
+            let names = template::names(&dir)?;
+            if names.is_empty() {
+                return Ok(format!("No templates in {}", dir.display()));
+            }
+            
+            let mut result = String::new();
+            for name in names {
+                match template::load(&dir, &name) {
+                    Ok(template) if template.description.is_empty() => result.push_str(&format!("{}\n", name)),
+                    Ok(template) => result.push_str(&format!("{} - {}\n", name, template.description)),
+                    Err(e) => result.push_str(&format!("{} (unusable: {})\n", name, e)),
+                }
             }
+            Ok(result)
         },
         
-        Command::Referral(specialty) => {
+        Command::ApplyTemplate { name } => {
+            let template = template::load(&TEMPLATE_DIR.lock().unwrap(), &name)?;
+            let mut entries = Vec::new();
+            let mut notes = Vec::new();
+            for section in &template.sections {
+                let (entry, note) = section_entry(&section.section, section.text.clone());
+                entries.push((section.section.clone(), entry));
+                notes.extend(note);
+            }
+            let order_count = template.orders.len();
+            
             let mut encounter_manager = ENCOUNTER_MANAGER.lock().unwrap();
-            let action = format!("Referral: {}", specialty);
-            match encounter_manager.add_action(action) {
-                Ok(_) => format!("Referred to: \"{}\"", specialty),
-                Err(e) => format!("Error: {}", e),
+            // Each prescription is checked against the patient's allergies,
+            // the encounter and the template's earlier prescriptions
+            let allergies = current_allergies(&encounter_manager)?;
+            let mut medications = medications_in_use(&encounter_manager)?;
+            let mut allergy_notes = Vec::new();
+            let mut interactions = Vec::new();
+            for order in &template.orders {
+                if let ClinicalAction::Prescription(prescription) = order {
+                    allergy_notes.extend(allergy_warnings(prescription, &allergies)?);
+                    interactions.extend(interaction::check(&prescription.drug, &medications));
+                    medications.push(Medication {
+                        drug: prescription.drug.clone(),
+                        source: None,
+                    });
+                }
+            }
+            encounter_manager.apply_template(&template.name, entries, template.orders, interactions.clone())?;
+            let mut output = format!(
+                "Applied template {}: {} section entr{}, {} order(s)",
+                template.name,
+                template.sections.len(),
+                if template.sections.len() == 1 { "y" } else { "ies" },
+                order_count
+            );
+            for note in notes {
+                output.push_str(&format!("\n{}", note));
+            }
+            for note in allergy_notes {
+                output.push_str(&format!("\nWarning: {}", note));
+            }
+            output = with_interactions(output, &interactions);
+            let unfilled = encounter_manager.current_encounter()?.unfilled_placeholders();
+            if !unfilled.is_empty() {
+                output.push_str("\nFill in these placeholders before committing, e.g. fill <name> \"<text>\":");
+                for (section, names) in unfilled {
+                    let names: Vec<String> = names.iter().map(|name| format!("{{{{{}}}}}", name)).collect();
+                    output.push_str(&format!("\n  {}: {}", section, names.join(", ")));
+                }
             }
+            Ok(output)
         },
         
-        Command::CommitEncounter => {
+        Command::FillPlaceholder { placeholder, value } => {
             let mut encounter_manager = ENCOUNTER_MANAGER.lock().unwrap();
-            match encounter_manager.commit_encounter() {
-                Ok(filename) => format!("Encounter committed successfully and saved to {}", filename),
-                Err(e) => format!("Error: {}", e),
+            let filled = encounter_manager.fill_placeholder(&placeholder, &value)?;
+            let remaining: usize = encounter_manager
+                .current_encounter()?
+                .unfilled_placeholders()
+                .iter()
+                .map(|(_, names)| names.len())
+                .sum();
+            Ok(format!(
+                "Filled {{{{{}}}}} with \"{}\" in {} entr{}; {} placeholder(s) left",
+                placeholder,
+                value,
+                filled,
+                if filled == 1 { "y" } else { "ies" },
+                remaining
+            ))
+        },
+        
+        Command::RegisterAllergy { substance, criticality } => {
+            let encounter_manager = ENCOUNTER_MANAGER.lock().unwrap();
+            let id = current_patient(&encounter_manager)?.id;
+            let mut registry = PATIENT_REGISTRY.lock().unwrap();
+            registry.modify(&id, |patient| {
+                Ok(match patient.record_allergy(&substance, criticality)? {
+                    Some(previous) => format!(
+                        "Updated allergy to {} for {}: {} criticality (was {})",
+                        substance.trim(),
+                        patient.name,
+                        criticality,
+                        previous
+                    ),
+                    None => format!(
+                        "Recorded allergy to {} for {}: {} criticality",
+                        substance.trim(),
+                        patient.name,
+                        criticality
+                    ),
+                })
+            })
+        },
+        
+        Command::RegisterSection { section, details } => {
+            let mut encounter_manager = ENCOUNTER_MANAGER.lock().unwrap();
+            let (entry, note) = section_entry(&section, details.clone());
+            let count = encounter_manager.add_section(section.clone(), entry)?;
+            let output = if count > 1 {
+                format!("Added to {} section (entry {}): \"{}\"", section, count, details)
+            } else {
+                format!("Added to {} section: \"{}\"", section, details)
+            };
+            Ok(with_note(output, note))
+        },
+        
+        Command::AmendSection { section, details } => {
+            let mut encounter_manager = ENCOUNTER_MANAGER.lock().unwrap();
+            let (entry, note) = section_entry(&section, details.clone());
+            let previous = encounter_manager.amend_section(&section, entry)?;
+            let output = format!(
+                "Amended last {} entry: \"{}\" (was \"{}\")",
+                section, details, previous.text
+            );
+            Ok(with_note(output, note))
+        },
+        
+        Command::ReplaceSection { section, details } => {
+            let mut encounter_manager = ENCOUNTER_MANAGER.lock().unwrap();
+            let (entry, note) = section_entry(&section, details.clone());
+            let dropped = encounter_manager.replace_section(section.clone(), entry)?;
+            let output = format!(
+                "Replaced {} section ({} earlier entr{} removed): \"{}\"",
+                section,
+                dropped,
+                if dropped == 1 { "y" } else { "ies" },
+                details
+            );
+            Ok(with_note(output, note))
+        },
+        
+        Command::ClearSection { section } => {
+            let mut encounter_manager = ENCOUNTER_MANAGER.lock().unwrap();
+            let dropped = encounter_manager.clear_section(&section)?;
+            Ok(format!(
+                "Cleared {} section ({} entr{} removed)",
+                section,
+                dropped,
+                if dropped == 1 { "y" } else { "ies" }
+            ))
+        },
+        
+        Command::Prescribe(prescription) => {
+            let mut encounter_manager = ENCOUNTER_MANAGER.lock().unwrap();
+            let summary = prescription.to_string();
+            let structured = prescription.structured;
+            let allergies = current_allergies(&encounter_manager)?;
+            let allergy_notes = allergy_warnings(&prescription, &allergies)?;
+            let interactions = interaction::check(&prescription.drug, &medications_in_use(&encounter_manager)?);
+            encounter_manager.prescribe(prescription, interactions.clone())?;
+            let mut output = if structured {
+                format!("Prescribed: \"{}\"", summary)
+            } else {
+                format!(
+                    "Prescribed: \"{}\"\nWarning: free-text prescription is unstructured; \
+                     write it as prescribe <drug> <strength> <unit> <route> <frequency> \
+                     [for <n> days] [#<quantity>] [refills <n>]",
+                    summary
+                )
+            };
+            for note in allergy_notes {
+                output.push_str(&format!("\nWarning: {}", note));
             }
+            Ok(with_interactions(output, &interactions))
         },
         
-        Command::Unknown(cmd) => {
-            format!("Unknown command: \"{}\"", cmd)
-        }
+        Command::Request(exam) => {
+            let mut encounter_manager = ENCOUNTER_MANAGER.lock().unwrap();
+            let summary = exam.to_string();
+            encounter_manager.add_action(ClinicalAction::ExamRequest(exam))?;
+            Ok(format!("Requested: \"{}\"", summary))
+        },
+        
+        Command::Referral(referral) => {
+            let mut encounter_manager = ENCOUNTER_MANAGER.lock().unwrap();
+            let summary = referral.to_string();
+            encounter_manager.add_action(ClinicalAction::Referral(referral))?;
+            Ok(format!("Referred to: \"{}\"", summary))
+        },
+        
+        Command::RankDiagnosis { number, to } => {
+            let mut encounter_manager = ENCOUNTER_MANAGER.lock().unwrap();
+            let (diagnosis, rank) = encounter_manager.rank_diagnosis(number, to)?;
+            let role = if rank == 1 { "primary" } else { "secondary" };
+            Ok(format!("Diagnosis \"{}\" is now #{} ({})", diagnosis.text, rank, role))
+        },
+        
+        Command::MarkDiagnosis { number, status } => {
+            let mut encounter_manager = ENCOUNTER_MANAGER.lock().unwrap();
+            let diagnosis = encounter_manager.mark_diagnosis(number, status)?;
+            Ok(format!("Diagnosis #{} \"{}\" marked {}", number, diagnosis.text, status))
+        },
+        
+        Command::ShowPatient => {
+            let encounter_manager = ENCOUNTER_MANAGER.lock().unwrap();
+            Ok(describe_patient(&current_patient(&encounter_manager)?))
+        },
+        
+        Command::UpdatePatient { field, value } => {
+            let encounter_manager = ENCOUNTER_MANAGER.lock().unwrap();
+            let id = current_patient(&encounter_manager)?.id;
+            let mut registry = PATIENT_REGISTRY.lock().unwrap();
+            let summary = registry.modify(&id, |patient| {
+                patient.update(&field, &value)?;
+                Ok(describe_patient(patient))
+            })?;
+            Ok(format!("Updated {}\n{}", field, summary))
+        },
+        
+        Command::ReviewEncounter => {
+            let encounter_manager = ENCOUNTER_MANAGER.lock().unwrap();
+            Ok(review::render_encounter(encounter_manager.current_encounter()?))
+        },
+        
+        Command::ShowSection { section } => {
+            let encounter_manager = ENCOUNTER_MANAGER.lock().unwrap();
+            Ok(review::render_section(encounter_manager.current_encounter()?, &section))
+        },
+        
+        Command::History(target) => {
+            let patient = history_patient(target)?;
+            let encounter_manager = ENCOUNTER_MANAGER.lock().unwrap();
+            let (visits, unreadable) = history::lookup(&encounter_manager.store, &patient)?;
+            Ok(history::render(&patient, &visits, &unreadable))
+        },
+        
+        Command::Undo => {
+            let mut encounter_manager = ENCOUNTER_MANAGER.lock().unwrap();
+            let description = encounter_manager.undo()?;
+            Ok(format!("Undid: {}", description))
+        },
+        
+        Command::Redo => {
+            let mut encounter_manager = ENCOUNTER_MANAGER.lock().unwrap();
+            let description = encounter_manager.redo()?;
+            Ok(format!("Redid: {}", description))
+        },
+        
+        Command::CommitEncounter { force } => {
+            let mut encounter_manager = ENCOUNTER_MANAGER.lock().unwrap();
+            let patient = current_patient(&encounter_manager)?;
+            let (filename, overridden) = encounter_manager.commit_encounter(force, &patient)?;
+            let mut output = format!("Encounter committed successfully and saved to {}", filename);
+            if !overridden.is_empty() {
+                output.push_str(&format!(
+                    "\nCommitted over {} validation warning(s), recorded in the encounter notes:\n  {}",
+                    overridden.len(),
+                    overridden.join("\n  ")
+                ));
+            }
+            Ok(output)
+        },
+        
+        Command::DefineAlias { name, expansion } => {
+            let previous = macros::define(&name, Definition::Alias(expansion.clone()))?;
+            let verb = if previous.is_some() { "Redefined" } else { "Defined" };
+            Ok(format!("{} alias {} = {}", verb, name, expansion))
+        },
+        
+        Command::DefineMacro { name, commands } => {
+            let count = commands.len();
+            let definition = Definition::Macro(commands);
+            let summary = definition.to_string();
+            let previous = macros::define(&name, definition)?;
+            let verb = if previous.is_some() { "Redefined" } else { "Defined" };
+            Ok(format!("{} macro {} = {} ({} command(s))", verb, name, summary, count))
+        },
+        
+        Command::Undefine { name } => match macros::remove(&name)? {
+            Definition::Alias(_) => Ok(format!("Removed alias {}", name)),
+            Definition::Macro(_) => Ok(format!("Removed macro {}", name)),
+        },
+        
+        Command::ListMacros => {
+            let definitions = macros::all();
+            if definitions.is_empty() {
+                return Ok(format!(
+                    "No aliases or macros defined; they are saved in {}",
+                    macros::path().display()
+                ));
+            }
+            
+            let mut aliases = String::new();
+            let mut macro_lines = String::new();
+            for (name, definition) in definitions {
+                match definition {
+                    Definition::Alias(_) => aliases.push_str(&format!("  {} = {}\n", name, definition)),
+                    Definition::Macro(_) => macro_lines.push_str(&format!("  {} = {}\n", name, definition)),
+                }
+            }
+            let mut result = String::new();
+            if !aliases.is_empty() {
+                result.push_str(&format!("Aliases:\n{}", aliases));
+            }
+            if !macro_lines.is_empty() {
+                result.push_str(&format!("Macros:\n{}", macro_lines));
+            }
+            result.push_str(&format!("Saved in {}", macros::path().display()));
+            Ok(result)
+        },
     }
 }
\ No newline at end of file
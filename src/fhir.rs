@@ -1,6 +1,20 @@
+// FHIR field names are camelCase in the JSON representation, so the structs mirror them verbatim
+#![allow(non_snake_case)]
+
 use serde::{Deserialize, Serialize};
-use chrono::Local;
 use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use crate::allergy;
+use crate::appointment::{Encounter as EncounterRecord, SectionEntry};
+use crate::clinical::{
+    ClinicalAction, DiagnosisStatus, ExamRequest, Exported, Prescription, Priority, Referral, TreatmentDuration,
+};
+use crate::clock;
+use crate::icd10;
+use crate::interaction::{Interaction, Severity};
+use crate::sections as section_catalogue;
+use crate::patient::{self, Patient as PatientRecord};
 
 // Basic FHIR Resource structure
 #[derive(Serialize, Deserialize, Debug)]
@@ -24,7 +38,12 @@ pub struct Encounter {
     pub status: String,
     pub class: Coding,
     pub subject: Reference,
+    #[serde(default)]
+    pub appointment: Vec<Reference>,
     pub period: Period,
+    // Time actually spent on the encounter, in minutes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub length: Option<Quantity>,
     pub reasonCode: Vec<CodeableConcept>,
     pub diagnosis: Vec<Diagnosis>,
     pub note: Vec<Annotation>,
@@ -37,8 +56,11 @@ pub struct Coding {
     pub display: String,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Reference {
+    // Empty for something that has no resource of its own, which is then
+    // described by the display text alone
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub reference: String,
     pub display: String,
 }
@@ -49,8 +71,9 @@ pub struct Period {
     pub end: Option<String>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Default)]
 pub struct CodeableConcept {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub coding: Vec<Coding>,
     pub text: String,
 }
@@ -58,61 +81,812 @@ pub struct CodeableConcept {
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Diagnosis {
     pub condition: Reference,
+    #[serde(rename = "use", default)]
+    pub r#use: CodeableConcept,
     pub rank: Option<u32>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Annotation {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub time: Option<String>,
+    pub text: String,
+}
+
+// FHIR Patient Resource
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Patient {
+    #[serde(flatten)]
+    pub resource: Resource,
+    #[serde(default)]
+    pub identifier: Vec<Identifier>,
+    pub name: Vec<HumanName>,
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub telecom: Vec<ContactPoint>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gender: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub birthDate: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Identifier {
+    #[serde(rename = "type")]
+    pub r#type: CodeableConcept,
+    pub system: String,
+    pub value: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ContactPoint {
+    pub system: String,
+    pub value: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct HumanName {
+    pub text: String,
+}
+
+// FHIR Condition Resource
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Condition {
+    #[serde(flatten)]
+    pub resource: Resource,
+    pub clinicalStatus: CodeableConcept,
+    pub verificationStatus: CodeableConcept,
+    pub category: Vec<CodeableConcept>,
+    pub code: CodeableConcept,
+    pub subject: Reference,
+    pub encounter: Reference,
+}
+
+// FHIR MedicationRequest Resource
+#[derive(Serialize, Deserialize, Debug)]
+pub struct MedicationRequest {
+    #[serde(flatten)]
+    pub resource: Resource,
+    pub status: String,
+    pub intent: String,
+    pub medicationCodeableConcept: CodeableConcept,
+    pub subject: Reference,
+    pub encounter: Reference,
+    pub authoredOn: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dosageInstruction: Vec<Dosage>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dispenseRequest: Option<DispenseRequest>,
+    // Why the drug was prescribed despite a recorded allergy
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub note: Vec<Annotation>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Dosage {
     pub text: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timing: Option<Timing>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub route: Option<CodeableConcept>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub doseAndRate: Vec<DoseAndRate>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Timing {
+    pub code: CodeableConcept,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DoseAndRate {
+    pub doseQuantity: Quantity,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DispenseRequest {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub numberOfRepeatsAllowed: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quantity: Option<Quantity>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expectedSupplyDuration: Option<Quantity>,
+}
+
+// FHIR Quantity, also used for Duration values (with a UCUM system and code)
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Quantity {
+    pub value: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unit: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub system: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
+}
+
+// FHIR ServiceRequest Resource, used for both exam requests and referrals
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ServiceRequest {
+    #[serde(flatten)]
+    pub resource: Resource,
+    pub status: String,
+    pub intent: String,
+    pub category: Vec<CodeableConcept>,
+    pub code: CodeableConcept,
+    pub subject: Reference,
+    pub encounter: Reference,
+    pub authoredOn: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priority: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub performerType: Vec<CodeableConcept>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub reasonCode: Vec<CodeableConcept>,
+}
+
+// FHIR DetectedIssue Resource, used for drug interactions
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DetectedIssue {
+    #[serde(flatten)]
+    pub resource: Resource,
+    pub status: String,
+    pub code: CodeableConcept,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub severity: Option<String>,
+    pub patient: Reference,
+    pub identifiedDateTime: String,
+    #[serde(default)]
+    pub implicated: Vec<Reference>,
+    #[serde(default)]
+    pub detail: String,
+}
+
+// FHIR AllergyIntolerance Resource, one per allergy in the patient registry
+#[derive(Serialize, Deserialize, Debug)]
+pub struct AllergyIntolerance {
+    #[serde(flatten)]
+    pub resource: Resource,
+    #[serde(default)]
+    pub identifier: Vec<Identifier>,
+    pub clinicalStatus: CodeableConcept,
+    #[serde(rename = "type")]
+    pub r#type: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub criticality: Option<String>,
+    pub code: CodeableConcept,
+    pub patient: Reference,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub recordedDate: Option<String>,
+}
+
+// FHIR Bundle Resource
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Bundle {
+    #[serde(flatten)]
+    pub resource: Resource,
+    pub r#type: String,
+    pub timestamp: String,
+    pub entry: Vec<BundleEntry>,
+}
+
+// A DELETE entry has neither a fullUrl nor a resource
+#[derive(Serialize, Deserialize, Debug)]
+pub struct BundleEntry {
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub fullUrl: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resource: Option<BundleResource>,
+    pub request: BundleRequest,
+}
+
+// Any resource that can appear in a Bundle entry. Resources we do not model
+// are kept as raw JSON so reading a Bundle back never fails on them.
+#[derive(Serialize, Debug)]
+#[serde(untagged)]
+pub enum BundleResource {
+    Patient(Patient),
+    Encounter(Encounter),
+    Condition(Condition),
+    MedicationRequest(MedicationRequest),
+    ServiceRequest(ServiceRequest),
+    DetectedIssue(DetectedIssue),
+    AllergyIntolerance(AllergyIntolerance),
+    Other(serde_json::Value),
+}
+
+impl<'de> Deserialize<'de> for BundleResource {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use serde::de::Error;
+
+        let value = serde_json::Value::deserialize(deserializer)?;
+        let resource = match value["resourceType"].as_str() {
+            Some("Patient") => serde_json::from_value(value).map(BundleResource::Patient),
+            Some("Encounter") => serde_json::from_value(value).map(BundleResource::Encounter),
+            Some("Condition") => serde_json::from_value(value).map(BundleResource::Condition),
+            Some("MedicationRequest") => {
+                serde_json::from_value(value).map(BundleResource::MedicationRequest)
+            }
+            Some("ServiceRequest") => {
+                serde_json::from_value(value).map(BundleResource::ServiceRequest)
+            }
+            Some("DetectedIssue") => {
+                serde_json::from_value(value).map(BundleResource::DetectedIssue)
+            }
+            Some("AllergyIntolerance") => {
+                serde_json::from_value(value).map(BundleResource::AllergyIntolerance)
+            }
+            _ => Ok(BundleResource::Other(value)),
+        };
+        resource.map_err(D::Error::custom)
+    }
+}
+
+impl BundleResource {
+    // `<type>/<id>` of a resource that belongs to one encounter and is updated
+    // by its addenda. Patients and allergies are shared between encounters.
+    fn owned_url(&self) -> Option<String> {
+        let resource = match self {
+            BundleResource::Encounter(encounter) => &encounter.resource,
+            BundleResource::Condition(condition) => &condition.resource,
+            BundleResource::MedicationRequest(request) => &request.resource,
+            BundleResource::ServiceRequest(request) => &request.resource,
+            BundleResource::DetectedIssue(issue) => &issue.resource,
+            _ => return None,
+        };
+        Some(format!("{}/{}", resource.resourceType, resource.id))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct BundleRequest {
+    pub method: String,
+    pub url: String,
+    // Conditional create: skip the POST when a matching resource exists
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ifNoneExist: Option<String>,
+}
+
+const SNOMED: &str = "http://snomed.info/sct";
+
+// Prefix of the MedicationRequest note recording an allergy override
+const ALLERGY_OVERRIDE_NOTE: &str = "Allergy override: ";
+
+// Deterministic UUID derived from a seed, so the same encounter always yields
+// the same fullUrls. Two FNV-1a passes with different offsets give 128 bits.
+fn uuid_from_seed(seed: &str) -> String {
+    let fnv = |offset: u64| {
+        seed.bytes().fold(offset, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+        })
+    };
+    // splitmix64 finalizer, so seeds differing in one byte give unrelated UUIDs
+    let mix = |mut x: u64| {
+        x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        x ^ (x >> 31)
+    };
+    let high = mix(fnv(0xcbf2_9ce4_8422_2325));
+    let low = mix(fnv(0x8422_2325_cbf2_9ce4));
+
+    // Mark as a version 4 (random) UUID in the RFC 4122 variant
+    let high = (high & 0xffff_ffff_ffff_0fff) | 0x0000_0000_0000_4000;
+    let low = (low & 0x3fff_ffff_ffff_ffff) | 0x8000_0000_0000_0000;
+
+    format!(
+        "{:08x}-{:04x}-{:04x}-{:04x}-{:012x}",
+        high >> 32,
+        (high >> 16) & 0xffff,
+        high & 0xffff,
+        low >> 48,
+        low & 0xffff_ffff_ffff
+    )
+}
+
+fn resource(resource_type: &str, id: String, now: &str) -> Resource {
+    Resource {
+        resourceType: resource_type.to_string(),
+        id,
+        meta: Meta {
+            versionId: "1".to_string(),
+            lastUpdated: now.to_string(),
+        },
+    }
+}
+
+fn concept(system: &str, code: &str, display: &str) -> CodeableConcept {
+    CodeableConcept {
+        coding: vec![Coding {
+            system: system.to_string(),
+            code: code.to_string(),
+            display: display.to_string(),
+        }],
+        text: display.to_string(),
+    }
+}
+
+fn text_concept(text: &str) -> CodeableConcept {
+    CodeableConcept {
+        coding: Vec::new(),
+        text: text.to_string(),
+    }
+}
+
+// Every entry of a section joined into one string, oldest first
+fn section_text(sections: &HashMap<String, Vec<SectionEntry>>, section: &str) -> Option<String> {
+    let entries = sections.get(section).filter(|entries| !entries.is_empty())?;
+    Some(entries.iter().map(|entry| entry.text.as_str()).collect::<Vec<_>>().join("; "))
+}
+
+fn bundle_entry(full_url: &str, resource_type: &str, resource: BundleResource) -> BundleEntry {
+    BundleEntry {
+        fullUrl: full_url.to_string(),
+        resource: Some(resource),
+        request: BundleRequest {
+            method: "POST".to_string(),
+            url: resource_type.to_string(),
+            ifNoneExist: None,
+        },
+    }
+}
+
+// Create or update a resource under the id it was given here, so a later
+// version of the encounter updates it instead of adding another
+fn put_entry(resource_type: &str, id: &str, resource: BundleResource) -> BundleEntry {
+    BundleEntry {
+        fullUrl: format!("urn:uuid:{}", id),
+        resource: Some(resource),
+        request: BundleRequest {
+            method: "PUT".to_string(),
+            url: format!("{}/{}", resource_type, id),
+            ifNoneExist: None,
+        },
+    }
+}
+
+// Build a transaction Bundle holding the Encounter together with the Patient,
+// Condition, MedicationRequest and ServiceRequest resources it refers to, and
+// the patient's AllergyIntolerance records. Resources are cross-linked through
+// their urn:uuid fullUrls. The encounter's own resources are PUT under their
+// UUIDs, so an addendum, which carries the ids read back from the version it
+// amends, updates them in place and deletes the ones it no longer has. Every
+// version is still a complete record of the visit.
+pub fn create_bundle(id: String, record: &EncounterRecord, patient: &PatientRecord) -> Bundle {
+    let sections = &record.sections;
+    let patient_name = record.patient_name.clone();
+    let reason = record.reason.clone();
+    let now = clock::now().to_rfc3339();
+    let seed = |kind: &str, index: usize| format!("{}|{}|{}|{}", id, now, kind, index);
+    let new_uuid = |kind: &str, index: usize| uuid_from_seed(&seed(kind, index));
+
+    let patient_uuid = new_uuid("Patient", 0);
+    let encounter_uuid = record
+        .amends
+        .as_ref()
+        .map(|amendment| amendment.resource_id.clone())
+        .filter(|id| !id.is_empty())
+        .unwrap_or_else(|| new_uuid("Encounter", 0));
+    let patient_ref = Reference {
+        reference: format!("urn:uuid:{}", patient_uuid),
+        display: patient_name,
+    };
+    let encounter_ref = Reference {
+        reference: format!("urn:uuid:{}", encounter_uuid),
+        display: reason.clone(),
+    };
+
+    // The patient is created only if the server has no record with this MRN
+    let mut patient_entry = bundle_entry(
+        &patient_ref.reference,
+        "Patient",
+        BundleResource::Patient(Patient {
+            resource: resource("Patient", patient_uuid, &now),
+            identifier: vec![Identifier {
+                r#type: concept("http://terminology.hl7.org/CodeSystem/v2-0203", "MR", "Medical record number"),
+                system: patient::MRN_SYSTEM.to_string(),
+                value: patient.id.clone(),
+            }],
+            name: vec![HumanName {
+                text: patient.name.clone(),
+            }],
+            telecom: patient
+                .contacts
+                .iter()
+                .map(|contact| ContactPoint {
+                    system: contact.system.clone(),
+                    value: contact.value.clone(),
+                })
+                .collect(),
+            gender: patient.sex.clone(),
+            birthDate: patient.birth_date.clone(),
+        }),
+    );
+    patient_entry.request.ifNoneExist = Some(format!("identifier={}|{}", patient::MRN_SYSTEM, patient.id));
+    let mut entries = vec![patient_entry];
+
+    // Allergies are created once per registry record, like the patient
+    for (index, allergy) in patient.allergies.iter().enumerate() {
+        let allergy_uuid = new_uuid("AllergyIntolerance", index);
+        let mut entry = bundle_entry(
+            &format!("urn:uuid:{}", allergy_uuid),
+            "AllergyIntolerance",
+            BundleResource::AllergyIntolerance(AllergyIntolerance {
+                resource: resource("AllergyIntolerance", allergy_uuid, &now),
+                identifier: vec![Identifier {
+                    r#type: text_concept("Allergy record"),
+                    system: allergy::SYSTEM.to_string(),
+                    value: allergy.id.clone(),
+                }],
+                clinicalStatus: concept(
+                    "http://terminology.hl7.org/CodeSystem/allergyintolerance-clinical",
+                    "active",
+                    "Active",
+                ),
+                r#type: "allergy".to_string(),
+                criticality: Some(allergy.criticality.code().to_string()),
+                code: text_concept(&allergy.substance),
+                patient: patient_ref.clone(),
+                recordedDate: Some(allergy.recorded_at.clone()),
+            }),
+        );
+        entry.request.ifNoneExist = Some(format!("identifier={}|{}", allergy::SYSTEM, allergy.id));
+        entries.push(entry);
+    }
+
+    // One condition per entry of the diagnosis section, coded with ICD-10 when
+    // the hypothesis was matched against the code table
+    let mut condition_refs = Vec::new();
+    let mut conditions = Vec::new();
+    let hypotheses = section_catalogue::diagnosis_section()
+        .and_then(|section| sections.get(&section))
+        .map(Vec::as_slice)
+        .unwrap_or_default();
+    for (index, hypothesis) in hypotheses.iter().enumerate() {
+        let status = hypothesis.status.unwrap_or(DiagnosisStatus::Provisional);
+        let condition_uuid = hypothesis.resource_id.clone().unwrap_or_else(|| new_uuid("Condition", index));
+        let mut code = text_concept(&hypothesis.text);
+        if let Some(icd10) = &hypothesis.code {
+            code.coding.push(Coding {
+                system: icd10::SYSTEM.to_string(),
+                code: icd10.code.clone(),
+                display: icd10.description.clone(),
+            });
+        }
+        let condition_ref = Reference {
+            reference: format!("urn:uuid:{}", condition_uuid),
+            display: match &hypothesis.code {
+                Some(icd10) => format!("{} {}", icd10.code, icd10.description),
+                None => hypothesis.text.clone(),
+            },
+        };
+        conditions.push(put_entry(
+            "Condition",
+            &condition_uuid,
+            BundleResource::Condition(Condition {
+                resource: resource("Condition", condition_uuid.clone(), &now),
+                clinicalStatus: concept(
+                    "http://terminology.hl7.org/CodeSystem/condition-clinical",
+                    "active",
+                    "Active",
+                ),
+                verificationStatus: concept(
+                    "http://terminology.hl7.org/CodeSystem/condition-ver-status",
+                    status.code(),
+                    match status {
+                        DiagnosisStatus::Provisional => "Provisional",
+                        DiagnosisStatus::Confirmed => "Confirmed",
+                        DiagnosisStatus::RuledOut => "Refuted",
+                    },
+                ),
+                category: vec![concept(
+                    "http://terminology.hl7.org/CodeSystem/condition-category",
+                    "encounter-diagnosis",
+                    "Encounter Diagnosis",
+                )],
+                code,
+                subject: patient_ref.clone(),
+                encounter: encounter_ref.clone(),
+            }),
+        ));
+        // Ruled-out hypotheses are kept as refuted conditions but not ranked
+        if status != DiagnosisStatus::RuledOut {
+            condition_refs.push(condition_ref);
+        }
+    }
+
+    // Turn clinical actions into order resources
+    // An order read back from a committed version keeps its id and the time
+    // it was first written
+    let order_uuids: Vec<String> = record
+        .actions
+        .iter()
+        .enumerate()
+        .map(|(index, action)| match action.exported() {
+            Some(exported) => exported.id.clone(),
+            None => new_uuid("Order", index),
+        })
+        .collect();
+    let mut orders = Vec::new();
+    for (action, order_uuid) in record.actions.iter().zip(&order_uuids) {
+        let authored_on = action.exported().map_or_else(|| now.clone(), |exported| exported.at.clone());
+
+        match action {
+            ClinicalAction::Prescription(prescription) => {
+                let dosage_instruction = if prescription.structured {
+                    vec![Dosage {
+                        text: prescription.dosage_text(),
+                        timing: prescription.frequency.as_deref().map(|frequency| Timing {
+                            code: text_concept(frequency),
+                        }),
+                        route: prescription.route.as_deref().map(text_concept),
+                        doseAndRate: prescription
+                            .strength
+                            .map(|strength| DoseAndRate {
+                                doseQuantity: Quantity {
+                                    value: strength,
+                                    unit: prescription.unit.clone(),
+                                    system: None,
+                                    code: None,
+                                },
+                            })
+                            .into_iter()
+                            .collect(),
+                    }]
+                } else {
+                    Vec::new()
+                };
+                let dispense_request = if prescription.quantity.is_some()
+                    || prescription.refills.is_some()
+                    || prescription.duration.is_some()
+                {
+                    Some(DispenseRequest {
+                        numberOfRepeatsAllowed: prescription.refills,
+                        quantity: prescription.quantity.map(|quantity| Quantity {
+                            value: quantity as f64,
+                            unit: None,
+                            system: None,
+                            code: None,
+                        }),
+                        expectedSupplyDuration: prescription.duration.as_ref().map(|duration| {
+                            Quantity {
+                                value: duration.value as f64,
+                                unit: Some(duration.unit.clone()),
+                                system: Some("http://unitsofmeasure.org".to_string()),
+                                code: Some(
+                                    match duration.unit.as_str() {
+                                        "weeks" => "wk",
+                                        "months" => "mo",
+                                        _ => "d",
+                                    }
+                                    .to_string(),
+                                ),
+                            }
+                        }),
+                    })
+                } else {
+                    None
+                };
+                orders.push(put_entry(
+                    "MedicationRequest",
+                    order_uuid,
+                    BundleResource::MedicationRequest(MedicationRequest {
+                        resource: resource("MedicationRequest", order_uuid.clone(), &now),
+                        status: "active".to_string(),
+                        intent: "order".to_string(),
+                        medicationCodeableConcept: text_concept(&prescription.drug),
+                        subject: patient_ref.clone(),
+                        encounter: encounter_ref.clone(),
+                        authoredOn: authored_on,
+                        dosageInstruction: dosage_instruction,
+                        dispenseRequest: dispense_request,
+                        note: prescription
+                            .allergy_override
+                            .iter()
+                            .map(|reason| Annotation {
+                                time: None,
+                                text: format!("{}{}", ALLERGY_OVERRIDE_NOTE, reason),
+                            })
+                            .collect(),
+                    }),
+                ));
+            }
+            ClinicalAction::ExamRequest(exam) => {
+                let mut code = text_concept(&exam.exam);
+                if let Some(loinc) = &exam.code {
+                    code.coding.push(Coding {
+                        system: "http://loinc.org".to_string(),
+                        code: loinc.clone(),
+                        display: exam.exam.clone(),
+                    });
+                }
+                orders.push(put_entry(
+                    "ServiceRequest",
+                    order_uuid,
+                    BundleResource::ServiceRequest(ServiceRequest {
+                        resource: resource("ServiceRequest", order_uuid.clone(), &now),
+                        status: "active".to_string(),
+                        intent: "order".to_string(),
+                        category: vec![concept(SNOMED, "103693007", "Diagnostic procedure")],
+                        code,
+                        subject: patient_ref.clone(),
+                        encounter: encounter_ref.clone(),
+                        authoredOn: authored_on,
+                        priority: Some(exam.priority.code().to_string()),
+                        performerType: Vec::new(),
+                        reasonCode: Vec::new(),
+                    }),
+                ));
+            }
+            ClinicalAction::Referral(referral) => {
+                orders.push(put_entry(
+                    "ServiceRequest",
+                    order_uuid,
+                    BundleResource::ServiceRequest(ServiceRequest {
+                        resource: resource("ServiceRequest", order_uuid.clone(), &now),
+                        status: "active".to_string(),
+                        intent: "order".to_string(),
+                        category: vec![concept(SNOMED, "3457005", "Patient referral")],
+                        code: text_concept(&format!("Referral to {}", referral.specialty)),
+                        subject: patient_ref.clone(),
+                        encounter: encounter_ref.clone(),
+                        authoredOn: authored_on,
+                        priority: None,
+                        performerType: vec![text_concept(&referral.specialty)],
+                        reasonCode: referral.reason.as_deref().map(text_concept).into_iter().collect(),
+                    }),
+                ));
+            }
+        }
+    }
+
+    // One issue per drug interaction, implicating the MedicationRequests
+    // involved. An active medication from an earlier encounter has no
+    // resource in this Bundle and is named instead.
+    let prescription_ref = |drug: &str| {
+        let index = record.actions.iter().rposition(|action| {
+            matches!(action, ClinicalAction::Prescription(prescription) if prescription.drug == drug)
+        });
+        Reference {
+            reference: index
+                .map(|index| format!("urn:uuid:{}", order_uuids[index]))
+                .unwrap_or_default(),
+            display: drug.to_string(),
+        }
+    };
+    let mut issues = Vec::new();
+    for (index, interaction) in record.interactions.iter().enumerate() {
+        let (issue_uuid, identified) = match &interaction.exported {
+            Some(exported) => (exported.id.clone(), exported.at.clone()),
+            None => (new_uuid("DetectedIssue", index), now.clone()),
+        };
+        let other = match &interaction.source {
+            Some(source) => Reference {
+                reference: String::new(),
+                display: format!("{} (active medication from {})", interaction.other, source),
+            },
+            None => prescription_ref(&interaction.other),
+        };
+        let mut code = concept("http://terminology.hl7.org/CodeSystem/v3-ActCode", "DRG", "Drug Interaction Alert");
+        code.text = format!("{} drug interaction", interaction.severity);
+        issues.push(put_entry(
+            "DetectedIssue",
+            &issue_uuid,
+            BundleResource::DetectedIssue(DetectedIssue {
+                resource: resource("DetectedIssue", issue_uuid.clone(), &now),
+                status: "final".to_string(),
+                code,
+                severity: Some(interaction.severity.fhir_code().to_string()),
+                patient: patient_ref.clone(),
+                identifiedDateTime: identified,
+                implicated: vec![prescription_ref(&interaction.drug), other],
+                detail: interaction.description.clone(),
+            }),
+        ));
+    }
+
+    let encounter = create_encounter(encounter_uuid.clone(), record, patient_ref, &condition_refs, &now);
+    entries.push(put_entry("Encounter", &encounter_uuid, BundleResource::Encounter(encounter)));
+    entries.extend(conditions);
+    entries.extend(orders);
+    entries.extend(issues);
+
+    // Resources of the amended version this one no longer has, e.g. an order
+    // taken back, are deleted
+    if let Some(amendment) = &record.amends {
+        let kept: Vec<String> = entries
+            .iter()
+            .filter_map(|entry| entry.resource.as_ref()?.owned_url())
+            .collect();
+        for url in amendment.exported.iter().filter(|url| !kept.contains(url)) {
+            entries.push(BundleEntry {
+                fullUrl: String::new(),
+                resource: None,
+                request: BundleRequest {
+                    method: "DELETE".to_string(),
+                    url: url.clone(),
+                    ifNoneExist: None,
+                },
+            });
+        }
+    }
+
+    Bundle {
+        resource: resource("Bundle", new_uuid("Bundle", 0), &now),
+        r#type: "transaction".to_string(),
+        timestamp: now.clone(),
+        entry: entries,
+    }
 }
 
 // Helper function to create a FHIR Encounter from our internal data structures
-pub fn create_encounter(
+fn create_encounter(
     id: String,
-    patient_name: String,
-    reason: String,
-    sections: &HashMap<String, String>,
-    actions: &Vec<String>,
+    record: &EncounterRecord,
+    subject: Reference,
+    conditions: &[Reference],
+    now: &str,
 ) -> Encounter {
-    let now = Local::now();
-    
-    // Create a diagnosis if there's a diagnostic hypothesis
-    let diagnoses = if let Some(diagnosis) = sections.get("diagnostic hypothesis") {
-        vec![Diagnosis {
-            condition: Reference {
-                reference: format!("Condition/{}", id),
-                display: diagnosis.clone(),
-            },
-            rank: Some(1),
-        }]
-    } else {
-        vec![]
-    };
+    let sections = &record.sections;
+    let reason = record.reason.clone();
+    // Point the diagnoses at the conditions created alongside the encounter,
+    // ranked from 1 in the order `review` lists them, so the first one is the
+    // primary diagnosis. They are the diagnoses the patient leaves the visit
+    // with, whatever their verification status, hence the discharge role
+    // rather than billing or comorbidity.
+    let diagnoses = conditions
+        .iter()
+        .enumerate()
+        .map(|(i, condition)| Diagnosis {
+            condition: condition.clone(),
+            r#use: concept("http://terminology.hl7.org/CodeSystem/diagnosis-role", "DD", "Discharge diagnosis"),
+            rank: Some(i as u32 + 1),
+        })
+        .collect();
     
-    // Create notes from sections
-    let mut notes = Vec::new();
-    for (section, content) in sections {
+    // One note per section entry. Sections follow the clinical order of the
+    // section catalogue and entries the order they were registered in, so the
+    // same encounter always exports the same notes.
+    let mut section_names: Vec<&String> = sections.keys().collect();
+    section_names.sort_by_key(|section| (section_catalogue::position(section), section.as_str()));
+    let mut notes: Vec<Annotation> = section_names
+        .into_iter()
+        .flat_map(|section| sections[section].iter().map(move |entry| (section, entry)))
+        .map(|(section, entry)| Annotation {
+            time: Some(entry.recorded_at.clone()).filter(|time| !time.is_empty()),
+            text: format!("{}: {}", section, entry.text),
+        })
+        .collect();
+    if let Some(amendment) = &record.amends {
         notes.push(Annotation {
-            text: format!("{}: {}", section, content),
+            time: Some(now.to_string()),
+            text: format!("Addendum: version {} amending {}", amendment.version, amendment.opened_from),
         });
     }
-    
-    // Add actions as notes
-    for action in actions {
+    if !record.overridden_warnings.is_empty() {
         notes.push(Annotation {
-            text: action.clone(),
+            time: Some(now.to_string()),
+            text: format!(
+                "Committed over validation warnings: {}",
+                record.overridden_warnings.join("; ")
+            ),
         });
     }
     
+    // Link back to the scheduled appointment this encounter fulfils
+    let appointment = Reference {
+        reference: format!("Appointment/{}", record.appointment_id),
+        display: reason.clone(),
+    };
+
     // Create reason code
-    let reason_code = if let Some(complaint) = sections.get("complaint") {
+    let complaint = section_catalogue::reason_section().and_then(|section| section_text(sections, &section));
+    let reason_code = if let Some(complaint) = complaint {
         vec![CodeableConcept {
             coding: vec![Coding {
                 system: "http://terminology.hl7.org/CodeSystem/reason-codes".to_string(),
                 code: "chief-complaint".to_string(),
                 display: "Chief complaint".to_string(),
             }],
-            text: complaint.clone(),
+            text: complaint,
         }]
     } else {
         vec![CodeableConcept {
@@ -125,31 +899,347 @@ pub fn create_encounter(
         }]
     };
     
+    // The period runs from `attend appointment` to the commit, and the length
+    // leaves out the time the encounter was set aside. An addendum keeps the
+    // visit's timing and carries the next version number.
+    let mut encounter_resource = resource("Encounter", id, now);
+    let (period, length_minutes) = match &record.amends {
+        Some(amendment) => {
+            encounter_resource.meta.versionId = amendment.version.to_string();
+            let period = Period {
+                start: amendment.period_start.clone(),
+                end: amendment.period_end.clone(),
+            };
+            (period, amendment.length_minutes)
+        }
+        None => {
+            let period = Period {
+                start: Some(record.started_at.clone())
+                    .filter(|start| !start.is_empty())
+                    .unwrap_or_else(|| now.to_string()),
+                end: Some(record.ended_at.clone().unwrap_or_else(|| now.to_string())),
+            };
+            (period, record.active_time().map(|time| (time.num_seconds() + 30) / 60))
+        }
+    };
+    let length = length_minutes.map(|minutes| Quantity {
+        value: minutes as f64,
+        unit: Some("min".to_string()),
+        system: Some("http://unitsofmeasure.org".to_string()),
+        code: Some("min".to_string()),
+    });
+
     Encounter {
-        resource: Resource {
-            resourceType: "Encounter".to_string(),
-            id: id.clone(),
-            meta: Meta {
-                versionId: "1".to_string(),
-                lastUpdated: now.to_rfc3339(),
-            },
-        },
+        resource: encounter_resource,
         status: "finished".to_string(),
-        class: Coding {
-            system: "http://terminology.hl7.org/CodeSystem/v3-ActCode".to_string(),
-            code: "AMB".to_string(),
-            display: "ambulatory".to_string(),
-        },
-        subject: Reference {
-            reference: format!("Patient/{}", id),
-            display: patient_name,
-        },
-        period: Period {
-            start: now.to_rfc3339(),
-            end: Some(now.to_rfc3339()),
+        class: if record.virtual_visit {
+            Coding {
+                system: "http://terminology.hl7.org/CodeSystem/v3-ActCode".to_string(),
+                code: "VR".to_string(),
+                display: "virtual".to_string(),
+            }
+        } else {
+            Coding {
+                system: "http://terminology.hl7.org/CodeSystem/v3-ActCode".to_string(),
+                code: "AMB".to_string(),
+                display: "ambulatory".to_string(),
+            }
         },
+        subject,
+        appointment: vec![appointment],
+        period,
+        length,
         reasonCode: reason_code,
         diagnosis: diagnoses,
         note: notes,
     }
 }
+
+// A committed encounter file: a transaction Bundle, or a bare Encounter as
+// written before encounters were exported as Bundles
+pub enum Record {
+    Bundle(Bundle),
+    Encounter(Box<Encounter>),
+}
+
+impl Record {
+    pub fn read(path: &Path) -> Result<Self, String> {
+        let content = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let value: serde_json::Value = serde_json::from_str(&content).map_err(|e| e.to_string())?;
+        match value["resourceType"].as_str() {
+            Some("Bundle") => serde_json::from_value(value).map(Record::Bundle).map_err(|e| e.to_string()),
+            Some("Encounter") => serde_json::from_value(value)
+                .map(|encounter| Record::Encounter(Box::new(encounter)))
+                .map_err(|e| e.to_string()),
+            _ => Err("not a FHIR Bundle or Encounter".to_string()),
+        }
+    }
+
+    pub fn encounter(&self) -> Result<&Encounter, String> {
+        match self {
+            Record::Bundle(bundle) => bundle
+                .entry
+                .iter()
+                .find_map(|entry| match &entry.resource {
+                    Some(BundleResource::Encounter(encounter)) => Some(encounter),
+                    _ => None,
+                })
+                .ok_or_else(|| "Bundle has no Encounter".to_string()),
+            Record::Encounter(encounter) => Ok(encounter.as_ref()),
+        }
+    }
+
+    // Resources exported alongside the encounter; none in the old format
+    pub fn resources(&self) -> Vec<&BundleResource> {
+        match self {
+            Record::Bundle(bundle) => bundle.entry.iter().filter_map(|entry| entry.resource.as_ref()).collect(),
+            Record::Encounter(_) => Vec::new(),
+        }
+    }
+
+    // Id of the Encounter resource, which addenda update. Bundles written
+    // before encounters were PUT only agree with it in the fullUrl.
+    pub fn encounter_resource_id(&self) -> Option<String> {
+        let Record::Bundle(bundle) = self else {
+            return None;
+        };
+        bundle.entry.iter().find_map(|entry| match &entry.resource {
+            Some(BundleResource::Encounter(_)) => entry.fullUrl.strip_prefix("urn:uuid:").map(str::to_string),
+            _ => None,
+        })
+    }
+
+    // `<type>/<id>` of the resources this version of the encounter exported
+    // besides the Encounter itself
+    pub fn exported(&self) -> Vec<String> {
+        self.resources()
+            .into_iter()
+            .filter(|resource| !matches!(resource, BundleResource::Encounter(_)))
+            .filter_map(BundleResource::owned_url)
+            .collect()
+    }
+}
+
+// Number of the appointment an exported encounter fulfils, from its
+// `Appointment/<n>` reference
+fn appointment_number(encounter: &Encounter) -> Option<usize> {
+    encounter
+        .appointment
+        .first()
+        .and_then(|appointment| appointment.reference.strip_prefix("Appointment/")?.parse().ok())
+}
+
+// Rebuild an editable encounter from a committed record, the reverse of
+// create_bundle. The appointment number is 0 when the file does not name one.
+// Section entries come back from the encounter notes, diagnoses from the
+// Conditions and actions from the order resources. Old bare Encounters only
+// have notes, so their orders come back as free text.
+pub fn read_encounter(record: &Record) -> Result<EncounterRecord, String> {
+    let encounter = record.encounter()?;
+    let resources = record.resources();
+    let legacy = matches!(record, Record::Encounter(_));
+    let diagnosis_section = section_catalogue::diagnosis_section();
+
+    let mut sections: HashMap<String, Vec<SectionEntry>> = HashMap::new();
+    let mut actions = Vec::new();
+    let mut interactions = Vec::new();
+    // Note times of the diagnoses, matched to the Conditions by text below
+    let mut diagnosis_times: Vec<(String, String)> = Vec::new();
+    for note in &encounter.note {
+        let Some((label, text)) = note.text.split_once(": ") else {
+            continue;
+        };
+        let time = note.time.clone().unwrap_or_default();
+        match label {
+            _ if Some(label) == diagnosis_section.as_deref() && !legacy => {
+                diagnosis_times.push((text.to_string(), time))
+            }
+            _ if section_catalogue::is_known(label) => {
+                let mut entry = SectionEntry::new(text.to_string());
+                entry.recorded_at = time;
+                if Some(label) == diagnosis_section.as_deref() {
+                    if let icd10::Lookup::Found(code) = icd10::lookup(text) {
+                        entry.code = Some(code);
+                    }
+                }
+                sections.entry(label.to_string()).or_default().push(entry);
+            }
+            "Prescription" if legacy => {
+                actions.push(ClinicalAction::Prescription(Prescription::free_text(text.to_string())));
+            }
+            "Exam Request" if legacy => actions.push(ClinicalAction::ExamRequest(ExamRequest {
+                exam: text.to_string(),
+                code: None,
+                priority: Priority::Routine,
+                exported: None,
+            })),
+            "Referral" if legacy => actions.push(ClinicalAction::Referral(Referral {
+                specialty: text.to_string(),
+                reason: None,
+                exported: None,
+            })),
+            _ => {}
+        }
+    }
+
+    for resource in &resources {
+        match resource {
+            BundleResource::Condition(condition) => {
+                // Dropping the diagnoses would lose them from the next version
+                let Some(diagnosis_section) = &diagnosis_section else {
+                    return Err(
+                        "the record has diagnoses but the section catalogue has no condition section to hold them"
+                            .to_string(),
+                    );
+                };
+                let mut entry = SectionEntry::new(condition.code.text.clone());
+                entry.resource_id = Some(condition.resource.id.clone());
+                if let Some(index) = diagnosis_times.iter().position(|(text, _)| *text == entry.text) {
+                    entry.recorded_at = diagnosis_times.remove(index).1;
+                }
+                entry.code = condition
+                    .code
+                    .coding
+                    .iter()
+                    .find(|coding| coding.system == icd10::SYSTEM)
+                    .map(|coding| icd10::Icd10Code {
+                        code: coding.code.clone(),
+                        description: coding.display.clone(),
+                    });
+                entry.status = condition
+                    .verificationStatus
+                    .coding
+                    .first()
+                    .and_then(|coding| DiagnosisStatus::from_code(&coding.code));
+                sections.entry(diagnosis_section.clone()).or_default().push(entry);
+            }
+            BundleResource::MedicationRequest(request) => {
+                let dosage = request.dosageInstruction.first();
+                let dispense = request.dispenseRequest.as_ref();
+                let dose = dosage.and_then(|dosage| dosage.doseAndRate.first()).map(|dose| &dose.doseQuantity);
+                actions.push(ClinicalAction::Prescription(Prescription {
+                    drug: request.medicationCodeableConcept.text.clone(),
+                    strength: dose.map(|dose| dose.value),
+                    unit: dose.and_then(|dose| dose.unit.clone()),
+                    route: dosage.and_then(|dosage| dosage.route.as_ref()).map(|route| route.text.clone()),
+                    frequency: dosage.and_then(|dosage| dosage.timing.as_ref()).map(|timing| timing.code.text.clone()),
+                    duration: dispense
+                        .and_then(|dispense| dispense.expectedSupplyDuration.as_ref())
+                        .map(|duration| TreatmentDuration {
+                            value: duration.value as u32,
+                            unit: duration.unit.clone().unwrap_or_else(|| "days".to_string()),
+                        }),
+                    quantity: dispense
+                        .and_then(|dispense| dispense.quantity.as_ref())
+                        .map(|quantity| quantity.value as u32),
+                    refills: dispense.and_then(|dispense| dispense.numberOfRepeatsAllowed),
+                    structured: dosage.is_some(),
+                    allergy_override: request
+                        .note
+                        .iter()
+                        .find_map(|note| note.text.strip_prefix(ALLERGY_OVERRIDE_NOTE))
+                        .map(str::to_string),
+                    exported: Some(Exported {
+                        id: request.resource.id.clone(),
+                        at: request.authoredOn.clone(),
+                    }),
+                }));
+            }
+            BundleResource::ServiceRequest(request) => {
+                let exported = Some(Exported {
+                    id: request.resource.id.clone(),
+                    at: request.authoredOn.clone(),
+                });
+                let referral = request
+                    .category
+                    .iter()
+                    .flat_map(|category| &category.coding)
+                    .any(|coding| coding.code == "3457005");
+                if referral {
+                    actions.push(ClinicalAction::Referral(Referral {
+                        specialty: request
+                            .performerType
+                            .first()
+                            .map(|specialty| specialty.text.clone())
+                            .unwrap_or_else(|| request.code.text.clone()),
+                        reason: request.reasonCode.first().map(|reason| reason.text.clone()),
+                        exported,
+                    }));
+                } else {
+                    actions.push(ClinicalAction::ExamRequest(ExamRequest {
+                        exam: request.code.text.clone(),
+                        code: request
+                            .code
+                            .coding
+                            .iter()
+                            .find(|coding| coding.system == "http://loinc.org")
+                            .map(|coding| coding.code.clone()),
+                        priority: request
+                            .priority
+                            .as_deref()
+                            .and_then(Priority::parse)
+                            .unwrap_or(Priority::Routine),
+                        exported,
+                    }));
+                }
+            }
+            BundleResource::DetectedIssue(issue) => {
+                let severity = issue.code.text.split_whitespace().next().and_then(Severity::parse);
+                if let (Some(severity), [drug, other]) = (severity, issue.implicated.as_slice()) {
+                    let (other, source) = match other
+                        .display
+                        .strip_suffix(')')
+                        .and_then(|display| display.rsplit_once(" (active medication from "))
+                    {
+                        Some((drug, source)) if other.reference.is_empty() => (drug.to_string(), Some(source.to_string())),
+                        _ => (other.display.clone(), None),
+                    };
+                    interactions.push(Interaction {
+                        drug: drug.display.clone(),
+                        other,
+                        source,
+                        severity,
+                        description: issue.detail.clone(),
+                        exported: Some(Exported {
+                            id: issue.resource.id.clone(),
+                            at: issue.identifiedDateTime.clone(),
+                        }),
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let patient_id = resources.iter().find_map(|resource| match resource {
+        BundleResource::Patient(patient) => patient
+            .identifier
+            .iter()
+            .find(|identifier| identifier.system == patient::MRN_SYSTEM)
+            .map(|identifier| identifier.value.clone()),
+        _ => None,
+    });
+    let reason = match encounter.appointment.first() {
+        Some(appointment) => appointment.display.clone(),
+        None => encounter.reasonCode.first().map(|reason| reason.text.clone()).unwrap_or_default(),
+    };
+
+    Ok(EncounterRecord {
+        appointment_id: appointment_number(encounter).unwrap_or(0),
+        patient_id,
+        patient_name: encounter.subject.display.clone(),
+        reason,
+        sections,
+        actions,
+        interactions,
+        status: "in-progress".to_string(),
+        virtual_visit: encounter.class.code == "VR",
+        overridden_warnings: Vec::new(),
+        // The addendum is timed from now; the visit's own timing is kept
+        // with the amendment
+        started_at: clock::now().to_rfc3339(),
+        ended_at: None,
+        pauses: Vec::new(),
+        amends: None,
+    })
+}
@@ -0,0 +1,235 @@
+use crate::clock;
+use crate::fhir::{self, BundleResource, CodeableConcept, MedicationRequest, Record};
+use crate::icd10;
+use crate::patient;
+use crate::sections;
+use crate::store::EncounterStore;
+use chrono::{DateTime, Duration};
+use std::path::Path;
+
+// SNOMED categories used for exam requests and referrals in ServiceRequest
+const EXAM_CATEGORY: &str = "103693007";
+const REFERRAL_CATEGORY: &str = "3457005";
+
+// What a committed encounter file says about one visit
+pub struct Visit {
+    pub file: String,
+    pub start: String,
+    pub patient_id: Option<String>,
+    pub patient_name: String,
+    pub reason: String,
+    pub complaints: Vec<String>,
+    pub diagnoses: Vec<String>,
+    pub prescriptions: Vec<String>,
+    pub exams: Vec<String>,
+    pub referrals: Vec<String>,
+    // Drugs of the prescriptions that are still running
+    pub active_medications: Vec<String>,
+}
+
+// The patient whose history is being looked up. Files written before the
+// patient registry existed carry no MRN and are matched by name.
+pub struct PatientKey {
+    pub id: Option<String>,
+    pub name: String,
+}
+
+impl PatientKey {
+    fn matches(&self, visit: &Visit) -> bool {
+        match (&self.id, &visit.patient_id) {
+            (Some(id), Some(visit_id)) => id == visit_id,
+            _ => self.name.eq_ignore_ascii_case(&visit.patient_name),
+        }
+    }
+}
+
+fn concept_text(concept: &CodeableConcept) -> String {
+    if concept.text.is_empty() {
+        concept.coding.first().map(|coding| coding.display.clone()).unwrap_or_default()
+    } else {
+        concept.text.clone()
+    }
+}
+
+// Entries of the notes written for one section, e.g. "complaint: headache"
+fn section_notes(encounter: &fhir::Encounter, section: &str) -> Vec<String> {
+    let prefix = format!("{}: ", section);
+    encounter
+        .note
+        .iter()
+        .filter_map(|note| note.text.strip_prefix(&prefix).map(str::to_string))
+        .collect()
+}
+
+// An active prescription is running until its supply, refills included, runs
+// out. One written without a duration is taken as long-term medication.
+fn still_active(request: &MedicationRequest) -> bool {
+    if request.status != "active" {
+        return false;
+    }
+    let Some(dispense) = &request.dispenseRequest else {
+        return true;
+    };
+    let Some(supply) = &dispense.expectedSupplyDuration else {
+        return true;
+    };
+    let Ok(authored) = DateTime::parse_from_rfc3339(&request.authoredOn) else {
+        return true;
+    };
+    let days_per_unit = match supply.code.as_deref() {
+        Some("wk") => 7.0,
+        Some("mo") => 30.0,
+        _ => 1.0,
+    };
+    let fills = f64::from(dispense.numberOfRepeatsAllowed.unwrap_or(0) + 1);
+    let days = (supply.value * days_per_unit * fills).ceil() as i64;
+    authored + Duration::days(days) > clock::now()
+}
+
+fn visit_from_encounter(file: String, encounter: &fhir::Encounter) -> Visit {
+    Visit {
+        file,
+        start: encounter.period.start.clone(),
+        patient_id: None,
+        patient_name: encounter.subject.display.clone(),
+        reason: encounter.appointment.first().map(|appointment| appointment.display.clone()).unwrap_or_else(|| {
+            encounter.reasonCode.first().map(concept_text).unwrap_or_default()
+        }),
+        complaints: sections::reason_section()
+            .map(|section| section_notes(encounter, &section))
+            .unwrap_or_default(),
+        diagnoses: Vec::new(),
+        prescriptions: Vec::new(),
+        exams: Vec::new(),
+        referrals: Vec::new(),
+        active_medications: Vec::new(),
+    }
+}
+
+// Encounter files from before Bundles were written hold a bare Encounter, with
+// orders only recorded as notes
+fn visit_from_legacy(file: String, encounter: &fhir::Encounter) -> Visit {
+    let mut visit = visit_from_encounter(file, encounter);
+    visit.diagnoses = encounter.diagnosis.iter().map(|diagnosis| diagnosis.condition.display.clone()).collect();
+    visit.prescriptions = section_notes(encounter, "Prescription");
+    visit.exams = section_notes(encounter, "Exam Request");
+    visit.referrals = section_notes(encounter, "Referral");
+    visit
+}
+
+fn visit_from_bundle(file: String, record: &Record) -> Result<Visit, String> {
+    let mut visit = visit_from_encounter(file, record.encounter()?);
+    for resource in record.resources() {
+        match resource {
+            BundleResource::Patient(patient) => {
+                visit.patient_id = patient
+                    .identifier
+                    .iter()
+                    .find(|identifier| identifier.system == patient::MRN_SYSTEM)
+                    .map(|identifier| identifier.value.clone());
+            }
+            BundleResource::Condition(condition) => {
+                let mut diagnosis = match condition.code.coding.iter().find(|coding| coding.system == icd10::SYSTEM) {
+                    Some(coding) => format!("{} {}", coding.code, coding.display),
+                    None => concept_text(&condition.code),
+                };
+                match condition.verificationStatus.coding.first().map(|coding| coding.code.as_str()) {
+                    Some("refuted") => diagnosis.push_str(" (ruled out)"),
+                    Some(status) => diagnosis.push_str(&format!(" ({})", status)),
+                    None => {}
+                }
+                visit.diagnoses.push(diagnosis);
+            }
+            BundleResource::MedicationRequest(request) => {
+                let drug = concept_text(&request.medicationCodeableConcept);
+                if still_active(request) {
+                    visit.active_medications.push(drug.clone());
+                }
+                visit.prescriptions.push(match request.dosageInstruction.first() {
+                    Some(dosage) if !dosage.text.is_empty() => format!("{} {}", drug, dosage.text),
+                    _ => drug,
+                });
+            }
+            BundleResource::ServiceRequest(request) => {
+                let category = request.category.iter().flat_map(|category| &category.coding).map(|coding| coding.code.as_str());
+                let category: Vec<&str> = category.collect();
+                if category.contains(&REFERRAL_CATEGORY) {
+                    let specialty = request.performerType.first().map(concept_text).unwrap_or_else(|| concept_text(&request.code));
+                    visit.referrals.push(match request.reasonCode.first() {
+                        Some(reason) => format!("{} for {}", specialty, concept_text(reason)),
+                        None => specialty,
+                    });
+                } else if category.contains(&EXAM_CATEGORY) {
+                    visit.exams.push(concept_text(&request.code));
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(visit)
+}
+
+fn load_visit(path: &Path) -> Result<Visit, String> {
+    let file = path.file_name().and_then(|name| name.to_str()).unwrap_or_default().to_string();
+    match Record::read(path)? {
+        Record::Encounter(encounter) => Ok(visit_from_legacy(file, &encounter)),
+        record => visit_from_bundle(file, &record),
+    }
+}
+
+// Every committed visit of a patient, oldest first, along with the files that
+// could not be read
+pub fn lookup(store: &EncounterStore, key: &PatientKey) -> Result<(Vec<Visit>, Vec<String>), String> {
+    let mut visits = Vec::new();
+    let mut unreadable = Vec::new();
+    for (_, path) in store.list()? {
+        match load_visit(&path) {
+            Ok(visit) if key.matches(&visit) => visits.push(visit),
+            Ok(_) => {}
+            Err(e) => unreadable.push(format!("{}: {}", path.display(), e)),
+        }
+    }
+
+    visits.sort_by_key(|visit| DateTime::parse_from_rfc3339(&visit.start).ok());
+    Ok((visits, unreadable))
+}
+
+// Chronological summary of a patient's earlier visits
+pub fn render(key: &PatientKey, visits: &[Visit], unreadable: &[String]) -> String {
+    let patient = match &key.id {
+        Some(id) => format!("{} ({})", key.name, id),
+        None => key.name.clone(),
+    };
+    let mut output = if visits.is_empty() {
+        format!("No committed encounters for {}\n", patient)
+    } else {
+        format!("History for {}: {} encounter(s)\n", patient, visits.len())
+    };
+
+    for visit in visits {
+        let date = DateTime::parse_from_rfc3339(&visit.start)
+            .map(|start| start.format("%Y-%m-%d %H:%M").to_string())
+            .unwrap_or_else(|_| visit.start.clone());
+        output.push_str(&format!("\n{}  {}  ({})\n", date, visit.reason, visit.file));
+        let lines = [
+            ("Complaint", &visit.complaints),
+            ("Diagnoses", &visit.diagnoses),
+            ("Prescriptions", &visit.prescriptions),
+            ("Exams", &visit.exams),
+            ("Referrals", &visit.referrals),
+        ];
+        for (label, items) in lines {
+            if !items.is_empty() {
+                output.push_str(&format!("  {}: {}\n", label, items.join("; ")));
+            }
+        }
+    }
+
+    if !unreadable.is_empty() {
+        output.push_str(&format!("\nSkipped {} unreadable file(s):\n", unreadable.len()));
+        for file in unreadable {
+            output.push_str(&format!("  {}\n", file));
+        }
+    }
+    output
+}
@@ -0,0 +1,113 @@
+use crate::data;
+use serde::{Deserialize, Serialize};
+
+// ICD-10 code system URI used in FHIR codings
+pub const SYSTEM: &str = "http://hl7.org/fhir/sid/icd-10";
+
+// Most candidates listed when a search term is ambiguous
+const MAX_CANDIDATES: usize = 8;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Icd10Code {
+    pub code: String,
+    pub description: String,
+}
+
+// Outcome of looking up a diagnostic hypothesis in the code table
+pub enum Lookup {
+    // A code or search term that identifies a single entry
+    Found(Icd10Code),
+    // A search term matching several entries
+    Ambiguous(Vec<Icd10Code>),
+    // Something shaped like a code that the bundled table does not have
+    UnknownCode(String),
+    NotFound,
+}
+
+lazy_static::lazy_static! {
+    static ref TABLE: Vec<Icd10Code> = data::rows(data::ICD10)
+        .filter_map(|fields| {
+            let [code, description] = fields.as_slice() else {
+                return None;
+            };
+            Some(Icd10Code {
+                code: code.to_string(),
+                description: description.to_string(),
+            })
+        })
+        .collect();
+}
+
+// Canonical form of a word that looks like an ICD-10 code: M545 and m54.5
+// both become M54.5
+fn normalize_code(word: &str) -> Option<String> {
+    let word = word.to_uppercase().replace('.', "");
+    let mut chars = word.chars();
+    let letter = chars.next()?;
+    let digits: String = chars.collect();
+    let valid = letter.is_ascii_uppercase()
+        && (2..=4).contains(&digits.len())
+        && digits.chars().all(|c| c.is_ascii_digit());
+    if !valid {
+        return None;
+    }
+    let (category, subdivision) = digits.split_at(2);
+    if subdivision.is_empty() {
+        Some(format!("{}{}", letter, category))
+    } else {
+        Some(format!("{}{}.{}", letter, category, subdivision))
+    }
+}
+
+// Resolve a hypothesis to a code. A leading code ("M51" or "M51 disc
+// herniation") is looked up directly. A first word that only looks like a
+// code, as in "B12 deficiency", falls back to searching by text, and is only
+// reported as an unknown code when that finds nothing either.
+pub fn lookup(text: &str) -> Lookup {
+    let first_word = text.split_whitespace().next().unwrap_or("");
+    let Some(code) = normalize_code(first_word) else {
+        return search(text);
+    };
+    if let Some(entry) = TABLE.iter().find(|entry| entry.code == code) {
+        return Lookup::Found(entry.clone());
+    }
+    match search(text) {
+        Lookup::NotFound => Lookup::UnknownCode(code),
+        found => found,
+    }
+}
+
+// Every word of the text must appear in the description. A description
+// matching the text exactly (or with only ", unspecified" added) wins over
+// broader matches.
+fn search(text: &str) -> Lookup {
+    let query = text.trim().to_lowercase();
+    let words: Vec<&str> = query
+        .split(|c: char| !c.is_alphanumeric() && c != '-')
+        .filter(|word| !word.is_empty())
+        .collect();
+    if words.is_empty() {
+        return Lookup::NotFound;
+    }
+
+    let matches: Vec<&Icd10Code> = TABLE
+        .iter()
+        .filter(|entry| {
+            let description = entry.description.to_lowercase();
+            words.iter().all(|word| description.contains(word))
+        })
+        .collect();
+
+    let exact = matches.iter().find(|entry| {
+        let description = entry.description.to_lowercase();
+        description == query || description == format!("{}, unspecified", query)
+    });
+    match (exact, matches.as_slice()) {
+        (Some(entry), _) => Lookup::Found((*entry).clone()),
+        (None, []) => Lookup::NotFound,
+        (None, [entry]) => Lookup::Found((*entry).clone()),
+        (None, entries) => Lookup::Ambiguous(
+            entries.iter().take(MAX_CANDIDATES).map(|entry| (*entry).clone()).collect(),
+        ),
+    }
+}
@@ -0,0 +1,145 @@
+use crate::clinical::Exported;
+use crate::data;
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::fmt;
+
+// How serious an interaction is, least serious first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Minor,
+    Moderate,
+    Major,
+    // The drugs should not be given together
+    Contraindicated,
+}
+
+impl Severity {
+    pub fn parse(text: &str) -> Option<Self> {
+        match text.to_lowercase().as_str() {
+            "minor" => Some(Severity::Minor),
+            "moderate" => Some(Severity::Moderate),
+            "major" => Some(Severity::Major),
+            "contraindicated" => Some(Severity::Contraindicated),
+            _ => None,
+        }
+    }
+
+    // FHIR DetectedIssue severity: high, moderate or low
+    pub fn fhir_code(&self) -> &'static str {
+        match self {
+            Severity::Contraindicated | Severity::Major => "high",
+            Severity::Moderate => "moderate",
+            Severity::Minor => "low",
+        }
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let label = match self {
+            Severity::Minor => "minor",
+            Severity::Moderate => "moderate",
+            Severity::Major => "major",
+            Severity::Contraindicated => "contraindicated",
+        };
+        write!(f, "{}", label)
+    }
+}
+
+struct Entry {
+    drugs: [Vec<String>; 2],
+    severity: Severity,
+    description: String,
+}
+
+lazy_static::lazy_static! {
+    static ref TABLE: Vec<Entry> = data::rows(data::INTERACTIONS)
+        .filter_map(|fields| {
+            let [first, second, severity, description] = fields.as_slice() else {
+                return None;
+            };
+            Some(Entry {
+                drugs: [words(first), words(second)],
+                severity: Severity::parse(severity)?,
+                description: description.to_string(),
+            })
+        })
+        .collect();
+}
+
+// A drug the new prescription may interact with
+pub struct Medication {
+    pub drug: String,
+    // Committed encounter file it was prescribed in, for the patient's active
+    // medications; None for a prescription of the current encounter
+    pub source: Option<String>,
+}
+
+// An interaction found when a drug was prescribed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Interaction {
+    // The drug being prescribed and the one it interacts with, as written
+    pub drug: String,
+    pub other: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    pub severity: Severity,
+    pub description: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exported: Option<Exported>,
+}
+
+impl fmt::Display for Interaction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} interaction: {} + {}", self.severity, self.drug, self.other)?;
+        if let Some(source) = &self.source {
+            write!(f, " (active medication from {})", source)?;
+        }
+        write!(f, ": {}", self.description)
+    }
+}
+
+// Lowercase words of a drug name, so "Warfarin 5mg" matches warfarin.
+// Combination products are split into their parts, so
+// "sulfamethoxazole-trimethoprim" matches sulfamethoxazole.
+pub fn words(text: &str) -> Vec<String> {
+    text.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+// Whether the words of a table drug appear, in order, in a prescribed drug
+pub fn mentions(prescribed: &[String], drug: &[String]) -> bool {
+    !drug.is_empty() && prescribed.windows(drug.len()).any(|window| window == drug)
+}
+
+// Interactions between a drug being prescribed and each of `others`, most
+// severe first
+pub fn check(drug: &str, others: &[Medication]) -> Vec<Interaction> {
+    let prescribed = words(drug);
+    let mut found = Vec::new();
+    for other in others {
+        let other_words = words(&other.drug);
+        for entry in TABLE.iter() {
+            let [a, b] = &entry.drugs;
+            let matches = (mentions(&prescribed, a) && mentions(&other_words, b))
+                || (mentions(&prescribed, b) && mentions(&other_words, a));
+            if matches {
+                found.push(Interaction {
+                    drug: drug.to_string(),
+                    other: other.drug.clone(),
+                    source: other.source.clone(),
+                    severity: entry.severity,
+                    description: entry.description.clone(),
+                    exported: None,
+                });
+            }
+        }
+    }
+    found.sort_by_key(|interaction| Reverse(interaction.severity));
+    found
+}
@@ -0,0 +1,133 @@
+// Tokenizer for DSL command lines
+
+// Byte range of a token within the input line
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TokenKind {
+    // Bare word: keywords, names, codes such as q8h or 4548-4
+    Word(String),
+    // Unsigned decimal number, kept as written
+    Number(String),
+    // Double-quoted string with escapes resolved
+    Str(String),
+    // Single punctuation character: # ; =
+    Symbol(char),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub span: Span,
+}
+
+#[derive(Debug)]
+pub struct LexError {
+    pub message: String,
+    pub span: Span,
+}
+
+const SYMBOLS: &[char] = &['#', ';', '='];
+
+fn is_number(text: &str) -> bool {
+    text.starts_with(|c: char| c.is_ascii_digit())
+        && text.chars().all(|c| c.is_ascii_digit() || c == '.')
+        && text.matches('.').count() <= 1
+        && !text.ends_with('.')
+}
+
+pub fn tokenize(input: &str) -> Result<Vec<Token>, LexError> {
+    let mut tokens = Vec::new();
+    let mut chars = input.char_indices().peekable();
+
+    while let Some(&(start, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '"' {
+            chars.next();
+            let mut value = String::new();
+            let end = loop {
+                match chars.next() {
+                    Some((i, '"')) => break i + 1,
+                    Some((i, '\\')) => match chars.next() {
+                        Some((_, '"')) => value.push('"'),
+                        Some((_, '\\')) => value.push('\\'),
+                        Some((_, 'n')) => value.push('\n'),
+                        Some((_, 't')) => value.push('\t'),
+                        Some((j, other)) => {
+                            return Err(LexError {
+                                message: format!("unknown escape sequence \\{}", other),
+                                span: Span { start: i, end: j + other.len_utf8() },
+                            })
+                        }
+                        None => {
+                            return Err(LexError {
+                                message: "unterminated string".to_string(),
+                                span: Span { start, end: input.len() },
+                            })
+                        }
+                    },
+                    Some((_, other)) => value.push(other),
+                    None => {
+                        return Err(LexError {
+                            message: "unterminated string".to_string(),
+                            span: Span { start, end: input.len() },
+                        })
+                    }
+                }
+            };
+            tokens.push(Token {
+                kind: TokenKind::Str(value),
+                span: Span { start, end },
+            });
+        } else if c == '#' && !input[start + 1..].starts_with(|c: char| c.is_ascii_digit()) {
+            // Comment to the end of the line; #<digits> is a dispense quantity
+            break;
+        } else if SYMBOLS.contains(&c) {
+            chars.next();
+            tokens.push(Token {
+                kind: TokenKind::Symbol(c),
+                span: Span { start, end: start + 1 },
+            });
+        } else {
+            let mut end = start;
+            while let Some(&(i, c)) = chars.peek() {
+                if c.is_whitespace() || c == '"' || SYMBOLS.contains(&c) {
+                    break;
+                }
+                end = i + c.len_utf8();
+                chars.next();
+            }
+            let text = &input[start..end];
+
+            // A number with a unit attached (500mg, 1%) becomes two tokens
+            let split = text
+                .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+                .unwrap_or(text.len());
+            let (number, suffix) = text.split_at(split);
+            if is_number(number) && suffix.chars().all(|c| c.is_alphabetic() || c == '%') {
+                tokens.push(Token {
+                    kind: TokenKind::Number(number.to_string()),
+                    span: Span { start, end: start + split },
+                });
+                if !suffix.is_empty() {
+                    tokens.push(Token {
+                        kind: TokenKind::Word(suffix.to_string()),
+                        span: Span { start: start + split, end },
+                    });
+                }
+            } else {
+                tokens.push(Token {
+                    kind: TokenKind::Word(text.to_string()),
+                    span: Span { start, end },
+                });
+            }
+        }
+    }
+
+    Ok(tokens)
+}
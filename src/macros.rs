@@ -0,0 +1,190 @@
+use crate::parser;
+use crate::store;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+// A name the user defined with `define alias` or `define macro`
+#[derive(Debug, Clone, PartialEq)]
+pub enum Definition {
+    // Stands for the start of a command, e.g. rx for prescribe
+    Alias(String),
+    // Stands for several whole commands, run in order
+    Macro(Vec<String>),
+}
+
+impl fmt::Display for Definition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Definition::Alias(expansion) => write!(f, "{}", expansion),
+            Definition::Macro(commands) => write!(f, "{}", commands.join(" ; ")),
+        }
+    }
+}
+
+// Aliases and macros as stored in the user's macros file
+#[derive(Default, Serialize, Deserialize)]
+struct MacrosFile {
+    #[serde(default)]
+    aliases: BTreeMap<String, String>,
+    #[serde(default)]
+    macros: BTreeMap<String, Vec<String>>,
+}
+
+// The user's aliases and macros, persisted so they carry over between
+// sessions
+pub struct Macros {
+    path: PathBuf,
+    definitions: BTreeMap<String, Definition>,
+}
+
+lazy_static::lazy_static! {
+    static ref MACROS: Mutex<Macros> = Mutex::new(Macros {
+        path: default_path(),
+        definitions: BTreeMap::new(),
+    });
+}
+
+// $XDG_CONFIG_HOME/charcot/macros.json, falling back to ~/.config and then
+// to the working directory
+pub fn default_path() -> PathBuf {
+    let config_dir = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").filter(|dir| !dir.is_empty()).map(|home| PathBuf::from(home).join(".config")));
+    match config_dir {
+        Some(dir) => dir.join("charcot").join("macros.json"),
+        None => PathBuf::from("macros.json"),
+    }
+}
+
+// Check a name for an alias or macro: a lowercase word that does not shadow
+// a command
+pub fn check_name(name: &str) -> Result<(), String> {
+    let valid = name.starts_with(|c: char| c.is_ascii_lowercase())
+        && name.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+    if !valid {
+        return Err(format!(
+            "invalid name \"{}\" (expected a lowercase word of letters, digits and hyphens)",
+            name
+        ));
+    }
+    if parser::COMMANDS.contains(&name) || name == "exit" {
+        return Err(format!("\"{}\" is a command and cannot be redefined", name));
+    }
+    Ok(())
+}
+
+// An alias must expand to a command, so aliases never chain
+pub fn check_alias(expansion: &str) -> Result<(), String> {
+    let first_word = expansion.split_whitespace().next().unwrap_or("").to_lowercase();
+    if parser::COMMANDS.contains(&first_word.as_str()) {
+        Ok(())
+    } else {
+        Err(format!("alias must start with a command, found \"{}\"", expansion))
+    }
+}
+
+// Load the macros file; a missing file means nothing is defined yet
+pub fn load(path: &Path) -> Result<Macros, String> {
+    let file: MacrosFile = match fs::read_to_string(path) {
+        Ok(content) => serde_json::from_str(&content).map_err(|e| format!("Invalid macros {}: {}", path.display(), e))?,
+        Err(e) if e.kind() == ErrorKind::NotFound => MacrosFile::default(),
+        Err(e) => return Err(format!("Failed to read {}: {}", path.display(), e)),
+    };
+
+    let mut definitions = BTreeMap::new();
+    for (name, expansion) in file.aliases {
+        check_name(&name)
+            .and_then(|_| check_alias(&expansion))
+            .map_err(|e| format!("Invalid macros {}: {}", path.display(), e))?;
+        definitions.insert(name, Definition::Alias(expansion));
+    }
+    for (name, commands) in file.macros {
+        check_name(&name).map_err(|e| format!("Invalid macros {}: {}", path.display(), e))?;
+        if definitions.contains_key(&name) {
+            return Err(format!("Invalid macros {}: \"{}\" is both an alias and a macro", path.display(), name));
+        }
+        definitions.insert(name, Definition::Macro(commands));
+    }
+    Ok(Macros {
+        path: path.to_path_buf(),
+        definitions,
+    })
+}
+
+pub fn set_macros(macros: Macros) {
+    *MACROS.lock().unwrap() = macros;
+}
+
+// Write the macros file
+fn save(macros: &Macros) -> Result<(), String> {
+    let mut file = MacrosFile::default();
+    for (name, definition) in &macros.definitions {
+        match definition {
+            Definition::Alias(expansion) => {
+                file.aliases.insert(name.clone(), expansion.clone());
+            }
+            Definition::Macro(commands) => {
+                file.macros.insert(name.clone(), commands.clone());
+            }
+        }
+    }
+    if let Some(dir) = macros.path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+    }
+    let json = serde_json::to_string_pretty(&file).map_err(|e| format!("Failed to serialize macros: {}", e))?;
+    store::replace_atomic(&macros.path, &json)
+        .map_err(|e| format!("Failed to save {}: {}", macros.path.display(), e))
+}
+
+pub fn lookup(name: &str) -> Option<Definition> {
+    MACROS.lock().unwrap().definitions.get(name).cloned()
+}
+
+// Every definition, sorted by name
+pub fn all() -> Vec<(String, Definition)> {
+    let macros = MACROS.lock().unwrap();
+    macros.definitions.iter().map(|(name, definition)| (name.clone(), definition.clone())).collect()
+}
+
+pub fn names() -> Vec<String> {
+    MACROS.lock().unwrap().definitions.keys().cloned().collect()
+}
+
+// Where definitions are saved
+pub fn path() -> PathBuf {
+    MACROS.lock().unwrap().path.clone()
+}
+
+// Add or replace a definition and save it; returns the one it replaced
+pub fn define(name: &str, definition: Definition) -> Result<Option<Definition>, String> {
+    let mut macros = MACROS.lock().unwrap();
+    let previous = macros.definitions.insert(name.to_string(), definition);
+    if let Err(e) = save(&macros) {
+        match previous {
+            Some(previous) => macros.definitions.insert(name.to_string(), previous),
+            None => macros.definitions.remove(name),
+        };
+        return Err(e);
+    }
+    Ok(previous)
+}
+
+// Remove a definition and save the rest
+pub fn remove(name: &str) -> Result<Definition, String> {
+    let mut macros = MACROS.lock().unwrap();
+    let removed = macros
+        .definitions
+        .remove(name)
+        .ok_or_else(|| format!("No alias or macro named \"{}\"", name))?;
+    if let Err(e) = save(&macros) {
+        macros.definitions.insert(name.to_string(), removed);
+        return Err(e);
+    }
+    Ok(removed)
+}
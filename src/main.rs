@@ -1,25 +1,235 @@
+mod clinical;
+mod clock;
 mod command;
+mod data;
+mod draft;
+mod lexer;
+mod macros;
 mod parser;
+mod patient;
 mod executor;
 mod appointment;
 mod fhir;
+mod history;
+mod icd10;
+mod interaction;
+mod allergy;
+mod medication;
+mod review;
 mod autocomplete;
+mod schedule;
+mod script;
+mod sections;
+mod store;
+mod template;
+mod validation;
 
 use rustyline::error::ReadlineError;
-use rustyline::{DefaultEditor, Result};
+use rustyline::history::DefaultHistory;
+use rustyline::{Editor, Result};
 use autocomplete::MedicalDSLHelper;
+use appointment::AppointmentManager;
+use patient::PatientRegistry;
 use rustyline::Config;
+use std::path::PathBuf;
+
+const USAGE: &str = "Usage: charcot_dsl (--schedule <file.json|file.csv> | --demo) [--output-dir <dir>] [--sections <sections.json>] [--rules <rules.json>] [--templates <dir>] [--macros <macros.json>] [run <script.charcot> [--keep-going]]\n\
+(--schedule or --demo may be left out with run, which then starts with an empty schedule)";
+
+// Command line options
+struct Options {
+    schedule: Option<PathBuf>,
+    demo: bool,
+    output_dir: PathBuf,
+    sections: Option<PathBuf>,
+    rules: Option<PathBuf>,
+    templates: PathBuf,
+    macros: PathBuf,
+    script: Option<PathBuf>,
+    keep_going: bool,
+}
+
+fn parse_args(args: &[String]) -> std::result::Result<Options, String> {
+    let mut options = Options {
+        schedule: None,
+        demo: false,
+        output_dir: PathBuf::from("."),
+        sections: None,
+        rules: None,
+        templates: PathBuf::from("templates"),
+        macros: macros::default_path(),
+        script: None,
+        keep_going: false,
+    };
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--schedule" => match args.next() {
+                Some(path) => options.schedule = Some(PathBuf::from(path)),
+                None => return Err(USAGE.to_string()),
+            },
+            "--output-dir" => match args.next() {
+                Some(dir) => options.output_dir = PathBuf::from(dir),
+                None => return Err(USAGE.to_string()),
+            },
+            "--sections" => match args.next() {
+                Some(path) => options.sections = Some(PathBuf::from(path)),
+                None => return Err(USAGE.to_string()),
+            },
+            "--rules" => match args.next() {
+                Some(path) => options.rules = Some(PathBuf::from(path)),
+                None => return Err(USAGE.to_string()),
+            },
+            "--templates" => match args.next() {
+                Some(dir) => options.templates = PathBuf::from(dir),
+                None => return Err(USAGE.to_string()),
+            },
+            "--macros" => match args.next() {
+                Some(path) => options.macros = PathBuf::from(path),
+                None => return Err(USAGE.to_string()),
+            },
+            "--demo" => options.demo = true,
+            "run" => match args.next() {
+                Some(path) => options.script = Some(PathBuf::from(path)),
+                None => return Err(USAGE.to_string()),
+            },
+            "--keep-going" => options.keep_going = true,
+            _ => return Err(format!("Unknown argument \"{}\"\n{}", arg, USAGE)),
+        }
+    }
+
+    Ok(options)
+}
+
+// Build the appointment schedule from the command line options. A script
+// needs no schedule when it only works on committed encounters, macros or
+// history.
+fn load_schedule(options: &Options) -> std::result::Result<AppointmentManager, String> {
+    match (&options.schedule, options.demo) {
+        (Some(path), false) => AppointmentManager::from_file(path),
+        (None, true) => Ok(AppointmentManager::demo()),
+        (None, false) if options.script.is_some() => Ok(AppointmentManager::new(Vec::new())),
+        _ => Err(USAGE.to_string()),
+    }
+}
+
+// Open the patient registry in the output directory and link the schedule's
+// appointments to it, registering patients seen for the first time. Links
+// made or refused by name are printed for the physician to check.
+fn load_patients(
+    options: &Options,
+    manager: &mut AppointmentManager,
+) -> std::result::Result<PatientRegistry, String> {
+    let mut registry = PatientRegistry::load(&options.output_dir)?;
+    let mut added = 0;
+    if options.demo {
+        added += registry.add_missing(patient::demo_patients());
+    }
+    let (linked, report) = registry.link(manager.appointments_mut());
+    added += linked;
+    for line in report {
+        println!("Warning: {}", line);
+    }
+    if added > 0 {
+        registry.save()?;
+    }
+    Ok(registry)
+}
 
 fn main() -> Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let options = match parse_args(&args) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    };
+    if let Err(e) = clock::init_from_env() {
+        eprintln!("{}", e);
+        std::process::exit(2);
+    }
+    // The section catalogue shapes the default validation rules, so it is
+    // set before the encounter manager is first used
+    if let Some(path) = &options.sections {
+        match sections::load(path) {
+            Ok(catalogue) => sections::set_catalogue(catalogue),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(2);
+            }
+        }
+    }
+    match macros::load(&options.macros) {
+        Ok(definitions) => macros::set_macros(definitions),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    }
+    let mut manager = match load_schedule(&options) {
+        Ok(manager) => manager,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    };
+    match load_patients(&options, &mut manager) {
+        Ok(registry) => executor::set_patients(registry),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    }
+    executor::set_appointments(manager);
+    executor::set_output_dir(options.output_dir);
+    executor::set_template_dir(options.templates);
+    if let Some(path) = &options.rules {
+        match validation::load_rules(path) {
+            Ok(rules) => executor::set_validation_rules(rules),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(2);
+            }
+        }
+    }
+
+    // Script mode: run the file and exit instead of starting the REPL
+    if let Some(script) = &options.script {
+        if let Err(e) = script::run(script, options.keep_going) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
     println!("=== Medical DSL ===");
     println!("Type 'exit' to quit");
     println!("Press Tab for autocompletion");
     
+    // Offer to restore encounters left unfinished by a crash or Ctrl-C
+    match executor::pending_drafts() {
+        Ok((drafts, skipped)) => {
+            for problem in skipped {
+                println!("Warning: {}", problem);
+            }
+            if !drafts.is_empty() {
+                println!("Found {} unfinished encounter draft(s):", drafts.len());
+                for draft in drafts {
+                    println!("  {}", draft);
+                }
+                println!("Type 'resume encounter' to restore them.");
+            }
+        },
+        Err(e) => println!("Warning: {}", e),
+    }
+    
     // Configure rustyline with our custom helper
     let config = Config::builder()
         .auto_add_history(true)
         .build();
-    let mut rl = DefaultEditor::with_config(config)?;
+    let mut rl: Editor<MedicalDSLHelper, DefaultHistory> = Editor::with_config(config)?;
     
     // Enable tab completion
     let helper = MedicalDSLHelper::new();
@@ -30,13 +240,40 @@ fn main() -> Result<()> {
         match readline {
             Ok(line) => {
                 let input = line.trim();
-                if input == "exit" {
+                if input == "exit --force" {
                     break;
                 }
+                if input == "exit" {
+                    // Don't abandon uncommitted encounters without asking
+                    let open = executor::open_encounter_count();
+                    if open == 0 {
+                        break;
+                    }
+                    println!(
+                        "{} encounter(s) still open and uncommitted. Commit or discard them, \
+                         or type 'exit --force' to quit and resume them from their drafts later.",
+                        open
+                    );
+                    continue;
+                }
                 
-                let command = parser::parse(input);
-                let output = executor::execute(command);
-                println!("{}", output);
+                if input.is_empty() || input.starts_with('#') {
+                    continue;
+                }
+                
+                match parser::parse_line(input) {
+                    // A macro runs its commands in order, stopping at the first failure
+                    Ok(commands) => for command in commands {
+                        match executor::execute(command) {
+                            Ok(output) => println!("{}", output),
+                            Err(e) => {
+                                println!("Error: {}", e);
+                                break;
+                            }
+                        }
+                    },
+                    Err(e) => println!("{}", e.render(input)),
+                }
             },
             Err(ReadlineError::Interrupted) => {
                 println!("CTRL-C");
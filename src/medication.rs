@@ -0,0 +1,112 @@
+use crate::clinical::{Prescription, TreatmentDuration};
+use crate::lexer::TokenKind;
+use crate::parser::{ParseError, Parser};
+
+// Medication order grammar:
+//
+//   <drug...> <strength>[ ]<unit> <route> <frequency> [for <n> <days|weeks|months>] [#<quantity>] [refills <n>]
+//       [override "<reason>"]
+//
+// e.g. `amoxicillin 500 mg po q8h for 7 days #21 refills 0`
+
+const UNITS: &[&str] = &[
+    "mg", "g", "mcg", "ml", "l", "units", "iu", "meq", "mmol", "tab", "tabs", "cap", "caps",
+    "puff", "puffs", "drop", "drops", "patch", "%",
+];
+
+const ROUTES: &[&str] = &[
+    "po", "sl", "iv", "im", "sc", "pr", "pv", "top", "td", "inh", "neb", "oph", "otic", "nasal",
+];
+
+const FREQUENCIES: &[&str] = &[
+    "once", "daily", "qd", "qam", "qpm", "qhs", "bid", "tid", "qid", "qod", "weekly", "monthly", "prn",
+];
+
+const DURATION_UNITS: &[&str] = &["days", "weeks", "months"];
+
+// Frequencies such as q8h, q4-6h or q2d
+fn is_interval(frequency: &str) -> bool {
+    let body = match frequency.strip_prefix('q') {
+        Some(body) if body.len() > 1 => body,
+        _ => return false,
+    };
+    let numbers = &body[..body.len() - 1];
+    matches!(body.chars().last(), Some('h' | 'd' | 'w'))
+        && numbers
+            .split('-')
+            .all(|n| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()))
+}
+
+// Parse a structured medication order from the remaining tokens of `parser`
+pub fn parse_order(parser: &mut Parser) -> Result<Prescription, ParseError> {
+    // Drug name: every word up to the strength
+    let start = parser.span();
+    let mut end = None;
+    while parser.peek_word().is_some() {
+        end = Some(parser.span());
+        parser.next();
+    }
+    let drug = match end {
+        Some(end) => parser.input()[start.start..end.end].to_string(),
+        None => return Err(parser.expected("a drug name")),
+    };
+
+    let strength = parser.expect_number(&format!("a strength after \"{}\"", drug))?;
+    let unit = parser.expect_one_of("a unit", UNITS)?;
+    let route = parser.expect_one_of("a route", ROUTES)?;
+
+    let frequency = match parser.peek_word() {
+        Some(word) if FREQUENCIES.contains(&word.as_str()) || is_interval(&word) => {
+            parser.next();
+            word
+        }
+        _ => return Err(parser.expected_one_of("a frequency", &[&["q<n>h"], FREQUENCIES].concat())),
+    };
+
+    let mut prescription = Prescription {
+        drug,
+        strength: Some(strength),
+        unit: Some(unit),
+        route: Some(route),
+        frequency: Some(frequency),
+        duration: None,
+        quantity: None,
+        refills: None,
+        structured: true,
+        allergy_override: None,
+        exported: None,
+    };
+
+    // Optional clauses, in any order, each at most once
+    while let Some(token) = parser.peek() {
+        let is_quantity = token.kind == TokenKind::Symbol('#');
+        let keyword = parser.peek_word();
+
+        if keyword.as_deref() == Some("for") && prescription.duration.is_none() {
+            parser.next();
+            let value = parser.expect_integer("a duration")?;
+            // Accept the singular too: for 1 day
+            let unit = match parser.peek_word() {
+                Some(word) if DURATION_UNITS.contains(&format!("{}s", word.trim_end_matches('s')).as_str()) => {
+                    parser.next();
+                    format!("{}s", word.trim_end_matches('s'))
+                }
+                _ => return Err(parser.expected_one_of("a duration unit", DURATION_UNITS)),
+            };
+            prescription.duration = Some(TreatmentDuration { value, unit });
+        } else if is_quantity && prescription.quantity.is_none() {
+            parser.next();
+            prescription.quantity = Some(parser.expect_integer("a dispense quantity")?);
+        } else if keyword.as_deref() == Some("refills") && prescription.refills.is_none() {
+            parser.next();
+            prescription.refills = Some(parser.expect_integer("the number of refills")?);
+        } else if keyword.as_deref() == Some("override") && prescription.allergy_override.is_none() {
+            parser.next();
+            prescription.allergy_override = Some(parser.expect_string("the reason for overriding the allergy")?);
+        } else {
+            return Err(parser.expected("for <n> days, #<quantity>, refills <n> or override \"<reason>\""));
+        }
+    }
+
+    Ok(prescription)
+}
@@ -1,53 +1,646 @@
-use regex::Regex;
-use crate::command::Command;
-
-pub fn parse(input: &str) -> Command {
-    let input = input.trim();
-    
-    // Basic commands
-    if input == "fetch appointments" {
-        return Command::FetchAppointments;
-    } else if input == "commit encounter" {
-        return Command::CommitEncounter;
-    }
-    
-    // Attend appointment
-    let attend_regex = Regex::new(r"^attend appointment (\d+)$").unwrap();
-    if let Some(captures) = attend_regex.captures(input) {
-        if let Some(num_match) = captures.get(1) {
-            if let Ok(num) = num_match.as_str().parse::<usize>() {
-                return Command::AttendAppointment(num);
-            }
-        }
-    }
-    
-    // Register section
-    let register_regex = Regex::new(r#"^register (complaint|physical exam|diagnostic hypothesis) "(.+)"$"#).unwrap();
-    if let Some(captures) = register_regex.captures(input) {
-        let section = captures.get(1).map_or("", |m| m.as_str()).to_string();
-        let details = captures.get(2).map_or("", |m| m.as_str()).to_string();
-        return Command::RegisterSection { section, details };
-    }
-    
-    // Clinical actions
-    let prescribe_regex = Regex::new(r#"^prescribe "(.+)"$"#).unwrap();
-    if let Some(captures) = prescribe_regex.captures(input) {
-        let details = captures.get(1).map_or("", |m| m.as_str()).to_string();
-        return Command::Prescribe(details);
-    }
-    
-    let request_regex = Regex::new(r#"^request "(.+)"$"#).unwrap();
-    if let Some(captures) = request_regex.captures(input) {
-        let details = captures.get(1).map_or("", |m| m.as_str()).to_string();
-        return Command::Request(details);
-    }
-    
-    let referral_regex = Regex::new(r#"^referral "(.+)"$"#).unwrap();
-    if let Some(captures) = referral_regex.captures(input) {
-        let details = captures.get(1).map_or("", |m| m.as_str()).to_string();
-        return Command::Referral(details);
-    }
-    
-    // If we got here, the command is unknown
-    Command::Unknown(input.to_string())
+use crate::allergy::{self, Criticality};
+use crate::clinical::{DiagnosisStatus, ExamRequest, Prescription, Priority, Referral};
+use crate::command::{Command, HistoryTarget};
+use crate::lexer::{self, Span, Token, TokenKind};
+use crate::macros::{self, Definition};
+use crate::medication;
+use crate::patient;
+use crate::sections;
+
+// Command keywords accepted at the start of a line
+pub const COMMANDS: &[&str] = &[
+    "fetch", "attend", "switch", "list", "discard", "resume", "open", "apply", "fill", "register", "amend", "replace",
+    "clear", "rank", "mark", "review", "show", "history", "update", "prescribe", "request", "referral", "undo", "redo",
+    "commit", "define", "undefine", "macros",
+];
+
+const PRIORITIES: &[&str] = &["routine", "urgent", "asap", "stat"];
+
+const DIAGNOSIS_STATUSES: &[&str] = &["provisional", "confirmed", "ruled-out"];
+
+// A parse failure, pointing at the part of the input that caused it
+#[derive(Debug)]
+pub struct ParseError {
+    pub message: String,
+    pub span: Span,
+    pub suggestion: Option<String>,
+}
+
+impl ParseError {
+    pub fn new(message: String, span: Span) -> Self {
+        Self {
+            message,
+            span,
+            suggestion: None,
+        }
+    }
+
+    // Render the error with the input line and a caret under the offending span
+    pub fn render(&self, input: &str) -> String {
+        let start = self.span.start.min(input.len());
+        let end = self.span.end.clamp(start, input.len());
+        let indent = input[..start].chars().count();
+        let width = input[start..end].chars().count().max(1);
+
+        let mut rendered = format!(
+            "Error: {}\n  {}\n  {}{}",
+            self.message,
+            input,
+            " ".repeat(indent),
+            "^".repeat(width)
+        );
+        if let Some(suggestion) = &self.suggestion {
+            rendered.push_str(&format!("\n  did you mean \"{}\"?", suggestion));
+        }
+        rendered
+    }
+}
+
+// Levenshtein distance between two words
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+// Closest candidate to a mistyped word, if it is close enough to be a likely typo
+pub fn suggest(word: &str, candidates: &[&str]) -> Option<String> {
+    let word = word.to_lowercase();
+    candidates
+        .iter()
+        .map(|candidate| (edit_distance(&word, candidate), candidate))
+        .filter(|(distance, _)| *distance > 0 && *distance <= (word.chars().count() / 3).max(1))
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate.to_string())
+}
+
+// Describe a token for error messages
+pub fn describe(token: Option<&Token>) -> String {
+    match token.map(|t| &t.kind) {
+        None => "end of input".to_string(),
+        Some(TokenKind::Word(word)) => format!("\"{}\"", word),
+        Some(TokenKind::Number(number)) => format!("number {}", number),
+        Some(TokenKind::Str(text)) => format!("string \"{}\"", text),
+        Some(TokenKind::Symbol(symbol)) => format!("\"{}\"", symbol),
+    }
+}
+
+// Recursive-descent parser over the tokens of one command line
+pub struct Parser<'a> {
+    input: &'a str,
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    pub fn new(input: &'a str) -> Result<Self, ParseError> {
+        let tokens = lexer::tokenize(input).map_err(|e| ParseError::new(e.message, e.span))?;
+        Ok(Self {
+            input,
+            tokens,
+            position: 0,
+        })
+    }
+
+    pub fn input(&self) -> &'a str {
+        self.input
+    }
+
+    pub fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    pub fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        if token.is_some() {
+            self.position += 1;
+        }
+        token
+    }
+
+    // Span of the next token, or of the position just past the input
+    pub fn span(&self) -> Span {
+        match self.peek() {
+            Some(token) => token.span,
+            None => Span {
+                start: self.input.len(),
+                end: self.input.len() + 1,
+            },
+        }
+    }
+
+    // Error at the next token: "expected <what>, found <token>"
+    pub fn expected(&self, what: &str) -> ParseError {
+        ParseError::new(
+            format!("expected {}, found {}", what, describe(self.peek())),
+            self.span(),
+        )
+    }
+
+    // Like `expected`, but for a word from a fixed list, with a typo suggestion
+    pub fn expected_one_of(&self, what: &str, options: &[&str]) -> ParseError {
+        let mut error = self.expected(&format!("{} ({})", what, options.join(", ")));
+        if let Some(Token { kind: TokenKind::Word(word), .. }) = self.peek() {
+            error.suggestion = suggest(word, options);
+        }
+        error
+    }
+
+    // Next token if it is a word, lowercased for keyword comparisons
+    pub fn peek_word(&self) -> Option<String> {
+        match self.peek() {
+            Some(Token { kind: TokenKind::Word(word), .. }) => Some(word.to_lowercase()),
+            _ => None,
+        }
+    }
+
+    pub fn expect_keyword(&mut self, keyword: &str) -> Result<(), ParseError> {
+        if self.peek_word().as_deref() == Some(keyword) {
+            self.next();
+            Ok(())
+        } else {
+            let mut error = self.expected(&format!("\"{}\"", keyword));
+            error.suggestion = self.peek_word().and_then(|word| suggest(&word, &[keyword]));
+            Err(error)
+        }
+    }
+
+    // A word from a fixed list, lowercased
+    pub fn expect_one_of(&mut self, what: &str, options: &[&str]) -> Result<String, ParseError> {
+        match self.peek_word() {
+            Some(word) if options.contains(&word.as_str()) => {
+                self.next();
+                Ok(word)
+            }
+            _ => Err(self.expected_one_of(what, options)),
+        }
+    }
+
+    pub fn expect_number(&mut self, what: &str) -> Result<f64, ParseError> {
+        match self.peek() {
+            Some(Token { kind: TokenKind::Number(number), .. }) => {
+                let value = number.parse().map_err(|_| self.expected(what))?;
+                self.next();
+                Ok(value)
+            }
+            _ => Err(self.expected(what)),
+        }
+    }
+
+    pub fn expect_integer(&mut self, what: &str) -> Result<u32, ParseError> {
+        match self.peek() {
+            Some(Token { kind: TokenKind::Number(number), .. }) => {
+                let value = number
+                    .parse()
+                    .map_err(|_| self.expected(&format!("{} as a whole number", what)))?;
+                self.next();
+                Ok(value)
+            }
+            _ => Err(self.expected(what)),
+        }
+    }
+
+    pub fn expect_string(&mut self, what: &str) -> Result<String, ParseError> {
+        match self.peek() {
+            Some(Token { kind: TokenKind::Str(text), .. }) => {
+                let text = text.clone();
+                self.next();
+                Ok(text)
+            }
+            _ => Err(self.expected(&format!("{} in double quotes", what))),
+        }
+    }
+
+    // Option value: a quoted string, a bare word or a number
+    pub fn expect_value(&mut self, what: &str) -> Result<String, ParseError> {
+        match self.peek().map(|t| t.kind.clone()) {
+            Some(TokenKind::Str(text) | TokenKind::Word(text) | TokenKind::Number(text)) => {
+                self.next();
+                Ok(text)
+            }
+            _ => Err(self.expected(what)),
+        }
+    }
+
+    pub fn expect_end(&mut self) -> Result<(), ParseError> {
+        match self.peek() {
+            None => Ok(()),
+            Some(token) => Err(ParseError::new(
+                format!("unexpected {} after end of command", describe(Some(token))),
+                token.span,
+            )),
+        }
+    }
+
+    pub fn parse_command(&mut self) -> Result<Command, ParseError> {
+        let keyword = match self.peek_word() {
+            Some(word) if COMMANDS.contains(&word.as_str()) => word,
+            Some(word) => {
+                let mut error = ParseError::new(format!("unknown command \"{}\"", word), self.span());
+                error.suggestion = suggest(&word, COMMANDS);
+                return Err(error);
+            }
+            None => return Err(self.expected(&format!("a command ({})", COMMANDS.join(", ")))),
+        };
+        self.next();
+
+        let command = match keyword.as_str() {
+            "fetch" => {
+                self.expect_keyword("appointments")?;
+                Command::FetchAppointments
+            }
+            "attend" => {
+                self.expect_keyword("appointment")?;
+                let number = self.expect_integer("an appointment number")?;
+                Command::AttendAppointment(number as usize)
+            }
+            "switch" => {
+                self.expect_keyword("to")?;
+                self.expect_keyword("appointment")?;
+                let number = self.expect_integer("an appointment number")?;
+                Command::SwitchEncounter(number as usize)
+            }
+            "list" => match self.expect_one_of("what to list", &["open", "templates"])?.as_str() {
+                "open" => {
+                    self.expect_keyword("encounters")?;
+                    Command::ListOpenEncounters
+                }
+                _ => Command::ListTemplates,
+            },
+            "discard" => {
+                self.expect_keyword("encounter")?;
+                let confirmed = self.peek_word().as_deref() == Some("--confirm");
+                if confirmed {
+                    self.next();
+                }
+                Command::DiscardEncounter { confirmed }
+            }
+            "resume" => {
+                self.expect_keyword("encounter")?;
+                Command::ResumeEncounter
+            }
+            "open" => {
+                self.expect_keyword("encounter")?;
+                let file = self.expect_value("an encounter file name")?;
+                Command::OpenEncounter { file }
+            }
+            "apply" => {
+                self.expect_keyword("template")?;
+                let name = self.expect_value("a template name")?;
+                Command::ApplyTemplate { name }
+            }
+            "fill" => {
+                // fill <placeholder> "<text>"; the braces around the name are optional
+                let placeholder = self.expect_value("a placeholder name")?;
+                let placeholder = placeholder.trim().trim_start_matches("{{").trim_end_matches("}}").trim().to_string();
+                let value = self.expect_string("the text to fill in")?;
+                Command::FillPlaceholder { placeholder, value }
+            }
+            "register" if matches!(self.peek_word().as_deref(), Some("allergy" | "allergies")) => {
+                // register allergy "<substance>" [severity <criticality>]; the
+                // plural is accepted too, so allergies always reach the registry
+                self.next();
+                let substance = self.expect_string("an allergy substance")?;
+                let mut criticality = Criticality::UnableToAssess;
+                if self.peek().is_some() {
+                    self.expect_one_of("an option", &["severity"])?;
+                    let value = self.expect_one_of("a severity", allergy::CRITICALITIES)?;
+                    criticality = Criticality::parse(&value).expect("severity checked against CRITICALITIES");
+                }
+                Command::RegisterAllergy { substance, criticality }
+            }
+            "register" => {
+                let section = self.parse_section()?;
+                let details = self.expect_string("section details")?;
+                Command::RegisterSection { section, details }
+            }
+            "amend" => {
+                let section = self.parse_section()?;
+                let details = self.expect_string("the amended text")?;
+                Command::AmendSection { section, details }
+            }
+            "replace" => {
+                let section = self.parse_section()?;
+                let details = self.expect_string("the new section text")?;
+                Command::ReplaceSection { section, details }
+            }
+            "clear" => Command::ClearSection {
+                section: self.parse_section()?,
+            },
+            "prescribe" => self.parse_prescribe()?,
+            "request" => self.parse_request()?,
+            "referral" => self.parse_referral()?,
+            "rank" => {
+                self.expect_keyword("diagnosis")?;
+                let number = self.expect_integer("a diagnosis number")? as usize;
+                // rank diagnosis <n> first | last | <rank>
+                let to = match self.peek().map(|t| &t.kind) {
+                    Some(TokenKind::Number(_)) => Some(self.expect_integer("a rank")? as usize),
+                    _ => match self.expect_one_of("a rank number or position", &["first", "last"])?.as_str() {
+                        "first" => Some(1),
+                        _ => None,
+                    },
+                };
+                Command::RankDiagnosis { number, to }
+            }
+            "mark" => {
+                self.expect_keyword("diagnosis")?;
+                let number = self.expect_integer("a diagnosis number")? as usize;
+                let status = self.expect_one_of("a diagnosis status", DIAGNOSIS_STATUSES)?;
+                let status = DiagnosisStatus::parse(&status).expect("status checked against DIAGNOSIS_STATUSES");
+                Command::MarkDiagnosis { number, status }
+            }
+            "review" => {
+                self.expect_keyword("encounter")?;
+                Command::ReviewEncounter
+            }
+            "show" if self.peek_word().as_deref() == Some("patient") => {
+                self.next();
+                Command::ShowPatient
+            }
+            "show" => Command::ShowSection {
+                section: self.parse_section()?,
+            },
+            "history" => {
+                // history [appointment <n> | <MRN or "patient name">]
+                let target = match self.peek_word().as_deref() {
+                    _ if self.peek().is_none() => HistoryTarget::Current,
+                    Some("appointment") => {
+                        self.next();
+                        HistoryTarget::Appointment(self.expect_integer("an appointment number")? as usize)
+                    }
+                    _ => HistoryTarget::Patient(self.expect_value("a medical record number or patient name")?),
+                };
+                Command::History(target)
+            }
+            "update" => {
+                // update patient <field> <value>
+                self.expect_keyword("patient")?;
+                let field = self.expect_one_of("a patient field", patient::FIELDS)?;
+                let value = self.expect_value(&format!("a {}", field))?;
+                Command::UpdatePatient { field, value }
+            }
+            "undo" => Command::Undo,
+            "redo" => Command::Redo,
+            "define" => self.parse_define()?,
+            "undefine" => {
+                let name = self.expect_value("an alias or macro name")?;
+                Command::Undefine { name: name.to_lowercase() }
+            }
+            "macros" => Command::ListMacros,
+            "commit" => {
+                self.expect_keyword("encounter")?;
+                let force = self.peek_word().as_deref() == Some("--force");
+                if force {
+                    self.next();
+                }
+                Command::CommitEncounter { force }
+            }
+            _ => unreachable!("keyword checked against COMMANDS"),
+        };
+
+        self.expect_end()?;
+        Ok(command)
+    }
+
+    // define alias <name> = <start of a command>
+    // define macro <name> = <command> ; <command> ...
+    fn parse_define(&mut self) -> Result<Command, ParseError> {
+        let kind = self.expect_one_of("what to define", &["alias", "macro"])?;
+        let name_span = self.span();
+        let name = self.peek_word().ok_or_else(|| self.expected(&format!("a name for the {}", kind)))?;
+        macros::check_name(&name).map_err(|e| ParseError::new(e, name_span))?;
+        self.next();
+        match self.peek().map(|t| &t.kind) {
+            Some(TokenKind::Symbol('=')) => {
+                self.next();
+            }
+            _ => return Err(self.expected("\"=\"")),
+        }
+
+        // The commands between the ";" separators, as written
+        let mut commands: Vec<(Span, String)> = Vec::new();
+        loop {
+            let first = self.span();
+            let mut last = None;
+            while let Some(token) = self.peek() {
+                if token.kind == TokenKind::Symbol(';') {
+                    break;
+                }
+                last = Some(token.span);
+                self.next();
+            }
+            let last = last.ok_or_else(|| self.expected("a command"))?;
+            let span = Span { start: first.start, end: last.end };
+            commands.push((span, self.input[span.start..span.end].to_string()));
+            if self.next().is_none() {
+                break;
+            }
+        }
+
+        if kind == "alias" {
+            if let [(_, expansion)] = commands.as_slice() {
+                macros::check_alias(expansion).map_err(|e| ParseError::new(e, commands[0].0))?;
+                return Ok(Command::DefineAlias { name, expansion: expansion.clone() });
+            }
+            let span = Span { start: commands[1].0.start, end: self.input.len() };
+            return Err(ParseError::new(
+                "an alias stands for a single command; use define macro for several".to_string(),
+                span,
+            ));
+        }
+
+        // Check each command now, so a typo is caught when the macro is defined
+        for (span, command) in &commands {
+            let first_word = command.split_whitespace().next().unwrap_or("").to_lowercase();
+            if matches!(macros::lookup(&first_word), Some(Definition::Macro(_))) {
+                return Err(ParseError::new("a macro cannot run another macro".to_string(), *span));
+            }
+            match parse_expanded(command) {
+                Ok(Command::DefineAlias { .. } | Command::DefineMacro { .. } | Command::Undefine { .. }) => {
+                    return Err(ParseError::new("a macro cannot define aliases or macros".to_string(), *span));
+                }
+                Ok(_) => {}
+                Err(mut e) => {
+                    e.span = Span {
+                        start: span.start + e.span.start,
+                        end: span.start + e.span.end,
+                    };
+                    return Err(e);
+                }
+            }
+        }
+        Ok(Command::DefineMacro {
+            name,
+            commands: commands.into_iter().map(|(_, command)| command).collect(),
+        })
+    }
+
+    // Section name after register, amend, replace, clear or show, from the
+    // section catalogue
+    fn parse_section(&mut self) -> Result<String, ParseError> {
+        let names = sections::names();
+        let names: Vec<&str> = names.iter().map(String::as_str).collect();
+        let first = self.position;
+        let start = self.span();
+        let mut words = Vec::new();
+        let mut end = start;
+        while let Some(word) = self.peek_word() {
+            end = self.span();
+            words.push(word);
+            self.next();
+        }
+
+        // Longest run of leading words naming a known section
+        let known = (1..=words.len())
+            .rev()
+            .find(|&count| names.contains(&words[..count].join(" ").as_str()));
+        match known {
+            Some(count) => {
+                self.position = first + count;
+                Ok(words[..count].join(" "))
+            }
+            None if words.is_empty() => {
+                Err(self.expected_one_of("a section", &names))
+            }
+            None => {
+                let section = words.join(" ");
+                let mut error = ParseError::new(
+                    format!("unknown section \"{}\" (expected {})", section, names.join(", ")),
+                    Span { start: start.start, end: end.end },
+                );
+                error.suggestion = suggest(&section, &names);
+                Err(error)
+            }
+        }
+    }
+
+    // prescribe "<free text>" [override "<reason>"] | prescribe <medication order>
+    fn parse_prescribe(&mut self) -> Result<Command, ParseError> {
+        if let Some(Token { kind: TokenKind::Str(text), .. }) = self.peek() {
+            let mut prescription = Prescription::free_text(text.clone());
+            self.next();
+            if self.peek().is_some() {
+                self.expect_one_of("an option", &["override"])?;
+                prescription.allergy_override = Some(self.expect_string("the reason for overriding the allergy")?);
+            }
+            return Ok(Command::Prescribe(prescription));
+        }
+        medication::parse_order(self).map(Command::Prescribe)
+    }
+
+    // request "<exam>" [code <code>] [priority <priority>]
+    fn parse_request(&mut self) -> Result<Command, ParseError> {
+        let exam = self.expect_string("an exam")?;
+        let mut request = ExamRequest {
+            exam,
+            code: None,
+            priority: Priority::Routine,
+            exported: None,
+        };
+        let mut priority_set = false;
+
+        while self.peek().is_some() {
+            let options: &[&str] = match (&request.code, priority_set) {
+                (None, false) => &["code", "priority"],
+                (None, true) => &["code"],
+                (Some(_), false) => &["priority"],
+                (Some(_), true) => break,
+            };
+            match self.expect_one_of("an option", options)?.as_str() {
+                "code" => request.code = Some(self.expect_value("an exam code")?),
+                _ => {
+                    let priority = self.expect_one_of("a priority", PRIORITIES)?;
+                    request.priority = Priority::parse(&priority).expect("priority checked against PRIORITIES");
+                    priority_set = true;
+                }
+            }
+        }
+
+        Ok(Command::Request(request))
+    }
+
+    // referral "<specialty>" [reason <reason>]
+    fn parse_referral(&mut self) -> Result<Command, ParseError> {
+        let specialty = self.expect_string("a specialty")?;
+        let mut reason = None;
+        if self.peek().is_some() {
+            self.expect_one_of("an option", &["reason"])?;
+            reason = Some(self.expect_value("a referral reason")?);
+        }
+        Ok(Command::Referral(Referral {
+            specialty,
+            reason,
+            exported: None,
+        }))
+    }
+}
+
+pub fn parse(input: &str) -> Result<Command, ParseError> {
+    Parser::new(input)?.parse_command()
+}
+
+// Parse a command that may start with an alias. Errors inside the alias's
+// expansion point at the alias.
+fn parse_expanded(input: &str) -> Result<Command, ParseError> {
+    let tokens = lexer::tokenize(input).map_err(|e| ParseError::new(e.message, e.span))?;
+    let alias = match tokens.first() {
+        Some(Token { kind: TokenKind::Word(word), span }) => match macros::lookup(&word.to_lowercase()) {
+            Some(Definition::Alias(expansion)) => Some((*span, expansion)),
+            _ => None,
+        },
+        _ => None,
+    };
+    let Some((span, expansion)) = alias else {
+        return parse(input);
+    };
+
+    let expanded = format!("{}{}", expansion, &input[span.end..]);
+    parse(&expanded).map_err(|mut e| {
+        let shift = expansion.len();
+        e.span = if e.span.start >= shift {
+            Span {
+                start: e.span.start - shift + span.end,
+                end: e.span.end - shift + span.end,
+            }
+        } else {
+            span
+        };
+        e
+    })
+}
+
+// Parse a line as typed: a command, a command starting with an alias, or the
+// name of a macro standing for several commands
+pub fn parse_line(input: &str) -> Result<Vec<Command>, ParseError> {
+    let tokens = lexer::tokenize(input).map_err(|e| ParseError::new(e.message, e.span))?;
+    if let Some(Token { kind: TokenKind::Word(word), span }) = tokens.first() {
+        if let Some(Definition::Macro(commands)) = macros::lookup(&word.to_lowercase()) {
+            if let Some(extra) = tokens.get(1) {
+                return Err(ParseError::new(
+                    format!("unexpected {} after macro {}", describe(Some(extra)), word),
+                    extra.span,
+                ));
+            }
+            // The macro's commands were checked when it was defined, but the
+            // aliases or sections they use may have changed since
+            return commands
+                .iter()
+                .map(|command| {
+                    parse_expanded(command).map_err(|e| {
+                        ParseError::new(format!("in macro {}, \"{}\": {}", word, command, e.message), *span)
+                    })
+                })
+                .collect();
+        }
+    }
+    Ok(vec![parse_expanded(input)?])
 }
@@ -0,0 +1,320 @@
+use crate::allergy::{Allergy, Criticality};
+use crate::appointment::Appointment;
+use crate::clock;
+use crate::store;
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+// Identifier system for the medical record numbers issued by the registry
+pub const MRN_SYSTEM: &str = "urn:charcot:mrn";
+
+// Fields that can be changed with `update patient`
+pub const FIELDS: &[&str] = &["birthdate", "sex", "phone", "email"];
+
+const SEXES: &[&str] = &["male", "female", "other", "unknown"];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Contact {
+    // "phone" or "email", as in FHIR ContactPoint.system
+    pub system: String,
+    pub value: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Patient {
+    // Medical record number
+    pub id: String,
+    pub name: String,
+    // YYYY-MM-DD
+    #[serde(default)]
+    pub birth_date: Option<String>,
+    // FHIR administrative gender: male, female, other or unknown
+    #[serde(default)]
+    pub sex: Option<String>,
+    #[serde(default)]
+    pub contacts: Vec<Contact>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allergies: Vec<Allergy>,
+}
+
+impl Patient {
+    pub fn new(id: String, name: String) -> Self {
+        Self {
+            id,
+            name,
+            birth_date: None,
+            sex: None,
+            contacts: Vec::new(),
+            allergies: Vec::new(),
+        }
+    }
+
+    // Record an allergy. Registering a substance again changes its
+    // criticality instead of adding a second record, and returns the
+    // criticality it had.
+    pub fn record_allergy(&mut self, substance: &str, criticality: Criticality) -> Result<Option<Criticality>, String> {
+        let substance = substance.trim();
+        if substance.is_empty() {
+            return Err("The allergy substance must not be empty".to_string());
+        }
+        if let Some(allergy) = self
+            .allergies
+            .iter_mut()
+            .find(|allergy| allergy.substance.eq_ignore_ascii_case(substance))
+        {
+            return Ok(Some(std::mem::replace(&mut allergy.criticality, criticality)));
+        }
+
+        let highest = self
+            .allergies
+            .iter()
+            .filter_map(|allergy| allergy.id.rsplit_once("-A")?.1.parse::<u32>().ok())
+            .max()
+            .unwrap_or(0);
+        self.allergies.push(Allergy {
+            id: format!("{}-A{}", self.id, highest + 1),
+            substance: substance.to_string(),
+            criticality,
+            recorded_at: clock::now().to_rfc3339(),
+        });
+        Ok(None)
+    }
+
+    // Set one of FIELDS, validating the value
+    pub fn update(&mut self, field: &str, value: &str) -> Result<(), String> {
+        let value = value.trim();
+        match field {
+            "birthdate" => {
+                NaiveDate::parse_from_str(value, "%Y-%m-%d")
+                    .map_err(|_| format!("Invalid birth date \"{}\" (expected YYYY-MM-DD)", value))?;
+                self.birth_date = Some(value.to_string());
+            }
+            "sex" => {
+                let sex = value.to_lowercase();
+                if !SEXES.contains(&sex.as_str()) {
+                    return Err(format!("Invalid sex \"{}\" (expected {})", value, SEXES.join(", ")));
+                }
+                self.sex = Some(sex);
+            }
+            "phone" | "email" => {
+                if value.is_empty() {
+                    return Err(format!("The {} must not be empty", field));
+                }
+                // One contact per system: a new number replaces the old one
+                self.contacts.retain(|contact| contact.system != field);
+                self.contacts.push(Contact {
+                    system: field.to_string(),
+                    value: value.to_string(),
+                });
+            }
+            _ => return Err(format!("Unknown patient field \"{}\" (expected {})", field, FIELDS.join(", "))),
+        }
+        Ok(())
+    }
+}
+
+// Patients known to this installation, persisted as patients.json in the
+// output directory so medical record numbers stay stable across sessions.
+// Several sessions may share the output directory, so the file is read again
+// before every change and before allergies are checked.
+pub struct PatientRegistry {
+    path: PathBuf,
+    patients: BTreeMap<String, Patient>,
+}
+
+impl PatientRegistry {
+    // Empty registry stored in `output_dir`
+    pub fn new(output_dir: &Path) -> Self {
+        Self {
+            path: output_dir.join("patients.json"),
+            patients: BTreeMap::new(),
+        }
+    }
+
+    pub fn load(output_dir: &Path) -> Result<Self, String> {
+        let path = output_dir.join("patients.json");
+        let patients = read(&path)?;
+        Ok(Self { path, patients })
+    }
+
+    // Pick up the changes other sessions saved. Their records replace the ones
+    // held here; patients only this session knows are kept.
+    pub fn reload(&mut self) -> Result<(), String> {
+        let mut patients = read(&self.path)?;
+        for (id, patient) in std::mem::take(&mut self.patients) {
+            patients.entry(id).or_insert(patient);
+        }
+        self.patients = patients;
+        Ok(())
+    }
+
+    // Save patients added in this session alongside those other sessions saved
+    pub fn save(&mut self) -> Result<(), String> {
+        self.reload()?;
+        self.write()
+    }
+
+    // Change one patient's record on top of the latest saved registry, and
+    // save it. Returns what `change` returned.
+    pub fn modify<T>(&mut self, id: &str, change: impl FnOnce(&mut Patient) -> Result<T, String>) -> Result<T, String> {
+        self.reload()?;
+        let patient = self
+            .patients
+            .get_mut(id)
+            .ok_or_else(|| format!("Patient {} is not registered", id))?;
+        let result = change(patient)?;
+        self.write()?;
+        Ok(result)
+    }
+
+    fn write(&self) -> Result<(), String> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+        }
+        let patients: Vec<&Patient> = self.patients.values().collect();
+        let json = serde_json::to_string_pretty(&patients)
+            .map_err(|e| format!("Failed to serialize patients: {}", e))?;
+        store::replace_atomic(&self.path, &json)
+            .map_err(|e| format!("Failed to save {}: {}", self.path.display(), e))
+    }
+
+    pub fn get(&self, id: &str) -> Option<&Patient> {
+        self.patients.get(id)
+    }
+
+    // Patients with this name, ignoring case
+    pub fn find_by_name(&self, name: &str) -> Vec<&Patient> {
+        self.patients
+            .values()
+            .filter(|patient| patient.name.eq_ignore_ascii_case(name))
+            .collect()
+    }
+
+    // Add patients that are not registered yet, leaving existing records
+    // alone. Returns how many were added.
+    pub fn add_missing(&mut self, patients: Vec<Patient>) -> usize {
+        let before = self.patients.len();
+        for patient in patients {
+            self.patients.entry(patient.id.clone()).or_insert(patient);
+        }
+        self.patients.len() - before
+    }
+
+    // Next free MRN-nnnnnn number
+    fn next_id(&self) -> String {
+        let highest = self
+            .patients
+            .keys()
+            .filter_map(|id| id.strip_prefix("MRN-")?.parse::<u32>().ok())
+            .max()
+            .unwrap_or(0);
+        format!("MRN-{:06}", highest + 1)
+    }
+
+    // Link every appointment to a registered patient. Appointments that name a
+    // patient ID register it if it is new. A name alone cannot tell two
+    // patients apart, so an appointment without an ID is only linked when its
+    // name is unique among those appointments and matches at most one
+    // registered patient, and each such link is reported. The others are left
+    // unlinked and reported as ambiguous. Returns how many patients were added
+    // and the report.
+    pub fn link(&mut self, appointments: &mut [Appointment]) -> (usize, Vec<String>) {
+        let mut added = 0;
+        let mut report = Vec::new();
+        let unidentified: Vec<String> = appointments
+            .iter()
+            .filter(|appointment| appointment.patient_id.is_none())
+            .map(|appointment| appointment.patient_name.to_lowercase())
+            .collect();
+        for appointment in appointments.iter_mut() {
+            if let Some(id) = &appointment.patient_id {
+                if !self.patients.contains_key(id) {
+                    self.patients
+                        .insert(id.clone(), Patient::new(id.clone(), appointment.patient_name.clone()));
+                    added += 1;
+                }
+                continue;
+            }
+
+            let name = appointment.patient_name.to_lowercase();
+            let scheduled = unidentified.iter().filter(|other| **other == name).count();
+            let registered: Vec<String> = self
+                .find_by_name(&appointment.patient_name)
+                .iter()
+                .map(|patient| patient.id.clone())
+                .collect();
+            match registered.as_slice() {
+                _ if scheduled > 1 => report.push(format!(
+                    "appointment {} not linked: {} has {} appointments without a patient_id",
+                    appointment.id, appointment.patient_name, scheduled
+                )),
+                [] => {
+                    let id = self.next_id();
+                    self.patients
+                        .insert(id.clone(), Patient::new(id.clone(), appointment.patient_name.clone()));
+                    added += 1;
+                    report.push(format!(
+                        "appointment {} has no patient_id; registered {} as new patient {}",
+                        appointment.id, appointment.patient_name, id
+                    ));
+                    appointment.patient_id = Some(id);
+                }
+                [id] => {
+                    report.push(format!(
+                        "appointment {} has no patient_id; linked to {} ({}) by name only",
+                        appointment.id, appointment.patient_name, id
+                    ));
+                    appointment.patient_id = Some(id.clone());
+                }
+                ids => report.push(format!(
+                    "appointment {} not linked: {} patients are named {} ({})",
+                    appointment.id,
+                    ids.len(),
+                    appointment.patient_name,
+                    ids.join(", ")
+                )),
+            }
+        }
+        (added, report)
+    }
+}
+
+// Patients saved in a registry file, by MRN; a missing file is an empty
+// registry
+fn read(path: &Path) -> Result<BTreeMap<String, Patient>, String> {
+    let patients: Vec<Patient> = match fs::read_to_string(path) {
+        Ok(content) => serde_json::from_str(&content)
+            .map_err(|e| format!("Invalid patient registry {}: {}", path.display(), e))?,
+        Err(e) if e.kind() == ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(format!("Failed to read {}: {}", path.display(), e)),
+    };
+    Ok(patients.into_iter().map(|patient| (patient.id.clone(), patient)).collect())
+}
+
+// Registry records for the --demo schedule
+pub fn demo_patients() -> Vec<Patient> {
+    let patient = |id: &str, name: &str, birth_date: &str, sex: &str| Patient {
+        id: id.to_string(),
+        name: name.to_string(),
+        birth_date: Some(birth_date.to_string()),
+        sex: Some(sex.to_string()),
+        contacts: Vec::new(),
+        allergies: Vec::new(),
+    };
+    vec![
+        patient("MRN-000001", "Jane Doe", "1984-03-12", "female"),
+        patient("MRN-000002", "John Smith", "1959-11-02", "male"),
+        patient("MRN-000003", "Mary Johnson", "1991-07-25", "female"),
+        patient("MRN-000004", "Robert Brown", "1966-01-30", "male"),
+        patient("MRN-000005", "Patricia Davis", "1978-09-14", "female"),
+        patient("MRN-000006", "Michael Wilson", "1955-05-08", "male"),
+        patient("MRN-000007", "Elizabeth Martinez", "1994-12-19", "female"),
+        patient("MRN-000008", "James Anderson", "1972-04-03", "male"),
+        patient("MRN-000009", "Jennifer Thomas", "1988-06-21", "female"),
+        patient("MRN-000010", "Charles Jackson", "1949-10-11", "male"),
+    ]
+}
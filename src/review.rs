@@ -0,0 +1,113 @@
+use crate::appointment::{Encounter, SectionEntry};
+use crate::clinical::DiagnosisStatus;
+use crate::sections::{self, Soap};
+use chrono::DateTime;
+
+// SOAP headings in order. Sections are filed under them as the section
+// catalogue says; the orders are listed under Plan after its sections.
+const SOAP: &[(&str, Soap)] = &[
+    ("Subjective", Soap::Subjective),
+    ("Objective", Soap::Objective),
+    ("Assessment", Soap::Assessment),
+    ("Plan", Soap::Plan),
+];
+
+// Clock time an entry was recorded, e.g. "09:42"
+fn entry_time(entry: &SectionEntry) -> Option<String> {
+    DateTime::parse_from_rfc3339(&entry.recorded_at)
+        .ok()
+        .map(|time| time.format("%H:%M").to_string())
+}
+
+// Diagnoses are numbered, as rank and mark refer to them by number
+fn render_entries(section: &str, entries: &[SectionEntry], output: &mut String) {
+    let diagnoses = sections::diagnosis_section().as_deref() == Some(section);
+    for (i, entry) in entries.iter().enumerate() {
+        let mut line = "    ".to_string();
+        if diagnoses {
+            line.push_str(&format!("{}. ", i + 1));
+        }
+        if let Some(time) = entry_time(entry) {
+            line.push_str(&format!("[{}] ", time));
+        }
+        line.push_str(&entry.text);
+        if let Some(code) = &entry.code {
+            line.push_str(&format!(" (ICD-10 {} {})", code.code, code.description));
+        }
+        if diagnoses {
+            line.push_str(&format!(" - {}", entry.status.unwrap_or(DiagnosisStatus::Provisional)));
+        }
+        output.push_str(&line);
+        output.push('\n');
+    }
+}
+
+// Render the in-progress encounter as a SOAP-style note for proofreading
+pub fn render_encounter(encounter: &Encounter) -> String {
+    let mut output = format!(
+        "Encounter for {} ({})\nReason: {}\n",
+        encounter.patient_name, encounter.status, encounter.reason
+    );
+    if let (Ok(start), Some(active)) = (DateTime::parse_from_rfc3339(&encounter.started_at), encounter.active_time()) {
+        output.push_str(&format!(
+            "Started: {} ({} min active)\n",
+            start.format("%H:%M"),
+            active.num_minutes()
+        ));
+    }
+
+    let catalogue = sections::all();
+    for (heading, soap) in SOAP {
+        output.push_str(&format!("\n{}\n", heading));
+        let mut empty = true;
+        for section in catalogue.iter().filter(|section| section.soap == *soap) {
+            if let Some(entries) = encounter.sections.get(&section.name) {
+                output.push_str(&format!("  {}:\n", section.name));
+                render_entries(&section.name, entries, &mut output);
+                empty = false;
+            }
+        }
+        if *soap != Soap::Plan {
+            if empty {
+                output.push_str("  (nothing registered)\n");
+            }
+            continue;
+        }
+
+        if encounter.actions.is_empty() {
+            output.push_str("  (no orders)\n");
+        }
+        for (i, action) in encounter.actions.iter().enumerate() {
+            output.push_str(&format!("  {}. {}\n", i + 1, action));
+        }
+        for interaction in &encounter.interactions {
+            output.push_str(&format!("  Warning: {}\n", interaction));
+        }
+    }
+
+    // Sections dropped from the catalogue since the encounter was drafted
+    let mut others: Vec<&String> = encounter.sections.keys().filter(|section| !sections::is_known(section)).collect();
+    others.sort();
+    if !others.is_empty() {
+        output.push_str("\nOther\n");
+        for section in others {
+            output.push_str(&format!("  {}:\n", section));
+            render_entries(section, &encounter.sections[section], &mut output);
+        }
+    }
+
+    output
+}
+
+// Render every entry of one section
+pub fn render_section(encounter: &Encounter, section: &str) -> String {
+    match encounter.sections.get(section) {
+        Some(entries) => {
+            let plural = if entries.len() == 1 { "y" } else { "ies" };
+            let mut output = format!("{} ({} entr{}):\n", section, entries.len(), plural);
+            render_entries(section, entries, &mut output);
+            output
+        }
+        None => format!("Nothing registered in the {} section yet\n", section),
+    }
+}
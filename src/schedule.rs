@@ -0,0 +1,413 @@
+use crate::appointment::Appointment;
+use chrono::{DateTime, NaiveTime};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::Path;
+
+// A single row of a JSON schedule file
+#[derive(Deserialize)]
+struct ScheduleRow {
+    id: Option<usize>,
+    // Medical record number; linked by name when absent
+    patient_id: Option<String>,
+    patient_name: String,
+    time: String,
+    reason: String,
+    // "in-person" (the default) or "virtual"
+    visit_type: Option<String>,
+}
+
+// Load a schedule file, picking the format from its extension (.json or .csv).
+// JSON files may hold either a plain array of rows or a FHIR Bundle of
+// Appointment/Slot resources. Every malformed row is reported, not just the first.
+pub fn load(path: &Path) -> Result<Vec<Appointment>, String> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read schedule {}: {}", path.display(), e))?;
+
+    let extension = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_lowercase());
+
+    let result = match extension.as_deref() {
+        Some("csv") => parse_csv(&content),
+        Some("json") => parse_json(&content),
+        _ => Err(vec![
+            "unsupported schedule format (expected .json or .csv)".to_string()
+        ]),
+    };
+
+    result.map_err(|problems| {
+        format!(
+            "Invalid schedule {}:\n  {}",
+            path.display(),
+            problems.join("\n  ")
+        )
+    })
+}
+
+fn parse_csv(content: &str) -> Result<Vec<Appointment>, Vec<String>> {
+    let mut lines = content
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line))
+        .filter(|(_, line)| !line.trim().is_empty());
+
+    let (header_line, header) = match lines.next() {
+        Some(header) => header,
+        None => return Err(vec!["schedule is empty".to_string()]),
+    };
+    let columns: Vec<String> = match split_csv_line(header) {
+        Ok(fields) => fields.iter().map(|f| f.trim().to_lowercase()).collect(),
+        Err(e) => return Err(vec![format!("line {}: {}", header_line, e)]),
+    };
+
+    let column = |name: &str| columns.iter().position(|c| c == name);
+    let id_column = column("id");
+    let visit_type_column = column("visit_type");
+    let patient_id_column = column("patient_id");
+    let (name_column, time_column, reason_column) =
+        match (column("patient_name"), column("time"), column("reason")) {
+            (Some(name), Some(time), Some(reason)) => (name, time, reason),
+            _ => {
+                return Err(vec![format!(
+                    "line {}: header must contain patient_name, time and reason columns",
+                    header_line
+                )])
+            }
+        };
+
+    let mut rows = Vec::new();
+    let mut problems = Vec::new();
+    for (line_number, line) in lines {
+        let location = format!("line {}", line_number);
+        let fields = match split_csv_line(line) {
+            Ok(fields) => fields,
+            Err(e) => {
+                problems.push(format!("{}: {}", location, e));
+                continue;
+            }
+        };
+        if fields.len() != columns.len() {
+            problems.push(format!(
+                "{}: expected {} fields, found {}",
+                location,
+                columns.len(),
+                fields.len()
+            ));
+            continue;
+        }
+
+        let id = match id_column.map(|i| fields[i].trim()) {
+            Some("") | None => None,
+            Some(value) => match value.parse::<usize>() {
+                Ok(id) => Some(id),
+                Err(_) => {
+                    problems.push(format!("{}: invalid id \"{}\"", location, value));
+                    continue;
+                }
+            },
+        };
+
+        rows.push((
+            location,
+            ScheduleRow {
+                id,
+                patient_id: patient_id_column.map(|i| fields[i].clone()),
+                patient_name: fields[name_column].clone(),
+                time: fields[time_column].clone(),
+                reason: fields[reason_column].clone(),
+                visit_type: visit_type_column.map(|i| fields[i].clone()),
+            },
+        ));
+    }
+
+    build_appointments(rows, problems)
+}
+
+// Split one CSV line into fields, honouring double-quoted fields with "" escapes
+fn split_csv_line(line: &str) -> Result<Vec<String>, String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut chars = line.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if in_quotes => {
+                if chars.peek() == Some(&'"') {
+                    field.push('"');
+                    chars.next();
+                } else {
+                    in_quotes = false;
+                }
+            }
+            '"' if field.trim().is_empty() => {
+                field.clear();
+                in_quotes = true;
+            }
+            ',' if !in_quotes => fields.push(std::mem::take(&mut field)),
+            _ => field.push(c),
+        }
+    }
+
+    if in_quotes {
+        return Err("unterminated quoted field".to_string());
+    }
+    fields.push(field);
+    Ok(fields)
+}
+
+fn parse_json(content: &str) -> Result<Vec<Appointment>, Vec<String>> {
+    let value: Value = serde_json::from_str(content)
+        .map_err(|e| vec![format!("malformed JSON: {}", e)])?;
+
+    match &value {
+        Value::Array(entries) => {
+            let lines = array_element_lines(content);
+            let mut rows = Vec::new();
+            let mut problems = Vec::new();
+            for (i, entry) in entries.iter().enumerate() {
+                let location = match lines.get(i) {
+                    Some(line) => format!("line {} (entry {})", line, i + 1),
+                    None => format!("entry {}", i + 1),
+                };
+                match serde_json::from_value::<ScheduleRow>(entry.clone()) {
+                    Ok(row) => rows.push((location, row)),
+                    Err(e) => problems.push(format!("{}: {}", location, e)),
+                }
+            }
+            build_appointments(rows, problems)
+        }
+        Value::Object(object) if object.get("resourceType") == Some(&Value::from("Bundle")) => {
+            parse_fhir_bundle(&value)
+        }
+        _ => Err(vec![
+            "expected an array of appointments or a FHIR Bundle".to_string()
+        ]),
+    }
+}
+
+// Line numbers on which each element of a top-level JSON array starts.
+// serde_json::Value does not keep positions, so scan the raw text instead.
+fn array_element_lines(content: &str) -> Vec<usize> {
+    let mut lines = Vec::new();
+    let mut line = 1;
+    let mut depth = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+    let mut expecting_element = false;
+
+    for c in content.chars() {
+        if c == '\n' {
+            line += 1;
+        }
+        if in_string {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        if expecting_element && !c.is_whitespace() && c != ']' {
+            lines.push(line);
+            expecting_element = false;
+        }
+        match c {
+            '"' => in_string = true,
+            '[' | '{' => {
+                depth += 1;
+                if depth == 1 && c == '[' {
+                    expecting_element = true;
+                }
+            }
+            ']' | '}' => depth -= 1,
+            ',' if depth == 1 => expecting_element = true,
+            _ => {}
+        }
+    }
+
+    lines
+}
+
+fn parse_fhir_bundle(bundle: &Value) -> Result<Vec<Appointment>, Vec<String>> {
+    let entries = match bundle.get("entry").and_then(Value::as_array) {
+        Some(entries) => entries,
+        None => return Err(vec!["Bundle has no entry array".to_string()]),
+    };
+    let resources: Vec<&Value> = entries.iter().map(|e| &e["resource"]).collect();
+
+    // Slots only contribute start times for appointments that reference them
+    let slot_starts: HashMap<String, &str> = resources
+        .iter()
+        .filter(|r| r["resourceType"] == "Slot")
+        .filter_map(|r| Some((format!("Slot/{}", r["id"].as_str()?), r["start"].as_str()?)))
+        .collect();
+
+    let mut rows = Vec::new();
+    let mut problems = Vec::new();
+    for (i, resource) in resources.iter().enumerate() {
+        if resource["resourceType"] != "Appointment" {
+            continue;
+        }
+        let location = format!("entry {}", i + 1);
+
+        // Appointments that will not happen do not belong on the day's list
+        if let Some("cancelled" | "noshow" | "entered-in-error") = resource["status"].as_str() {
+            continue;
+        }
+
+        let patient = resource["participant"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(|p| &p["actor"])
+            .find(|actor| {
+                actor["reference"]
+                    .as_str()
+                    .is_some_and(|r| r.starts_with("Patient/"))
+            });
+        let patient_name = patient.and_then(|actor| actor["display"].as_str());
+        let patient_id = patient
+            .and_then(|actor| actor["reference"].as_str()?.strip_prefix("Patient/"))
+            .map(str::to_string);
+
+        let start = resource["start"].as_str().or_else(|| {
+            resource["slot"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|slot| slot["reference"].as_str())
+                .find_map(|reference| slot_starts.get(reference).copied())
+        });
+
+        // Telehealth appointments carry the v3 ActCode VR as their appointmentType
+        let is_virtual = resource["appointmentType"]["coding"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .any(|coding| coding["code"] == "VR");
+
+        let reason = resource["reasonCode"][0]["text"]
+            .as_str()
+            .or_else(|| resource["description"].as_str());
+
+        let (patient_name, start, reason) = match (patient_name, start, reason) {
+            (Some(name), Some(start), Some(reason)) => (name, start, reason),
+            (None, _, _) => {
+                problems.push(format!("{}: Appointment has no Patient participant with a display name", location));
+                continue;
+            }
+            (_, None, _) => {
+                problems.push(format!("{}: Appointment has no start time or Slot", location));
+                continue;
+            }
+            (_, _, None) => {
+                problems.push(format!("{}: Appointment has no reasonCode text or description", location));
+                continue;
+            }
+        };
+
+        let time = match DateTime::parse_from_rfc3339(start) {
+            Ok(start) => start.format("%H:%M").to_string(),
+            Err(_) => {
+                problems.push(format!("{}: invalid start instant \"{}\"", location, start));
+                continue;
+            }
+        };
+
+        rows.push((
+            location,
+            ScheduleRow {
+                id: resource["id"].as_str().and_then(|id| id.parse().ok()),
+                patient_id,
+                patient_name: patient_name.to_string(),
+                time,
+                reason: reason.to_string(),
+                visit_type: Some(if is_virtual { "virtual" } else { "in-person" }.to_string()),
+            },
+        ));
+    }
+
+    build_appointments(rows, problems)
+}
+
+// Validate parsed rows and turn them into appointments. Rows without an explicit
+// id are numbered after their position in the file.
+fn build_appointments(
+    rows: Vec<(String, ScheduleRow)>,
+    mut problems: Vec<String>,
+) -> Result<Vec<Appointment>, Vec<String>> {
+    let mut appointments = Vec::new();
+    let mut seen_ids = HashMap::new();
+
+    for (position, (location, row)) in rows.into_iter().enumerate() {
+        let patient_name = row.patient_name.trim().to_string();
+        let reason = row.reason.trim().to_string();
+        let time = row.time.trim();
+
+        if patient_name.is_empty() {
+            problems.push(format!("{}: patient_name is empty", location));
+            continue;
+        }
+        if reason.is_empty() {
+            problems.push(format!("{}: reason is empty", location));
+            continue;
+        }
+        let time = match NaiveTime::parse_from_str(time, "%H:%M") {
+            Ok(time) => time.format("%H:%M").to_string(),
+            Err(_) => {
+                problems.push(format!("{}: invalid time \"{}\" (expected HH:MM)", location, time));
+                continue;
+            }
+        };
+
+        let virtual_visit = match row.visit_type.as_deref().map(|v| v.trim().to_lowercase()).as_deref() {
+            None | Some("") | Some("in-person") => false,
+            Some("virtual") => true,
+            Some(other) => {
+                problems.push(format!(
+                    "{}: invalid visit_type \"{}\" (expected in-person or virtual)",
+                    location, other
+                ));
+                continue;
+            }
+        };
+
+        let id = row.id.unwrap_or(position + 1);
+        // Appointment 0 stands for "no appointment" in encounter files
+        if id == 0 {
+            problems.push(format!("{}: invalid id 0 (ids start at 1)", location));
+            continue;
+        }
+        if let Some(previous) = seen_ids.insert(id, location.clone()) {
+            problems.push(format!("{}: duplicate id {} (first used at {})", location, id, previous));
+            continue;
+        }
+
+        let patient_id = row
+            .patient_id
+            .map(|id| id.trim().to_string())
+            .filter(|id| !id.is_empty());
+
+        appointments.push(Appointment {
+            id,
+            patient_id,
+            patient_name,
+            time,
+            reason,
+            virtual_visit,
+        });
+    }
+
+    if !problems.is_empty() {
+        return Err(problems);
+    }
+    if appointments.is_empty() {
+        return Err(vec!["schedule contains no appointments".to_string()]);
+    }
+    Ok(appointments)
+}
@@ -0,0 +1,59 @@
+use crate::executor;
+use crate::parser;
+use std::path::Path;
+
+// Run a file of DSL commands non-interactively, one command per line. Blank
+// lines and `#` comments are skipped. Execution stops at the first failing
+// line unless `keep_going` is set; either way an error is returned if any
+// line failed.
+pub fn run(path: &Path, keep_going: bool) -> Result<(), String> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read script {}: {}", path.display(), e))?;
+
+    let mut failures = 0;
+    for (index, line) in content.lines().enumerate() {
+        let line_number = index + 1;
+        let input = line.trim();
+        if input.is_empty() || input.starts_with('#') {
+            continue;
+        }
+        if input == "exit" {
+            break;
+        }
+
+        println!("> {}", input);
+        let diagnostic = match parser::parse_line(input) {
+            // A macro runs its commands in order, stopping at the first failure
+            Ok(commands) => match commands.into_iter().try_for_each(|command| {
+                println!("{}", executor::execute(command)?);
+                Ok::<(), String>(())
+            }) {
+                Ok(()) => continue,
+                Err(e) => format!("{}:{}: error: {}", path.display(), line_number, e),
+            },
+            Err(e) => {
+                let indent = line.len() - line.trim_start().len();
+                let column = line[..indent + e.span.start.min(input.len())].chars().count() + 1;
+                format!(
+                    "{}:{}:{}: {}",
+                    path.display(),
+                    line_number,
+                    column,
+                    e.render(input).replacen("Error: ", "error: ", 1)
+                )
+            }
+        };
+
+        eprintln!("{}", diagnostic);
+        failures += 1;
+        if !keep_going {
+            return Err(format!("Stopped at {}:{}", path.display(), line_number));
+        }
+    }
+
+    if failures > 0 {
+        Err(format!("{} command(s) failed in {}", failures, path.display()))
+    } else {
+        Ok(())
+    }
+}
@@ -0,0 +1,152 @@
+use serde::Deserialize;
+use std::path::Path;
+use std::sync::Mutex;
+
+// Catalogue used unless one is given with --sections, bundled with the binary
+const DEFAULT_CATALOGUE: &str = include_str!("../data/sections.json");
+
+// Part of the SOAP note a section is reviewed under
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Soap {
+    Subjective,
+    Objective,
+    Assessment,
+    Plan,
+}
+
+// What a section becomes in the FHIR export besides its Encounter notes
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Export {
+    #[default]
+    Note,
+    // The Encounter's chief complaint reasonCode
+    Reason,
+    // Ranked, ICD-10 coded Conditions; `rank diagnosis` and `mark diagnosis`
+    // work on this section
+    Condition,
+}
+
+// A section of the encounter note that `register` and friends accept
+#[derive(Debug, Clone, Deserialize)]
+pub struct Section {
+    pub name: String,
+    pub soap: Soap,
+    #[serde(default)]
+    pub export: Export,
+}
+
+lazy_static::lazy_static! {
+    static ref CATALOGUE: Mutex<Vec<Section>> =
+        Mutex::new(parse(DEFAULT_CATALOGUE).expect("bundled section catalogue is valid"));
+}
+
+// Parse and check a catalogue: a JSON array of sections in clinical order,
+// with unique lowercase names and at most one reason and condition section
+fn parse(content: &str) -> Result<Vec<Section>, String> {
+    let catalogue: Vec<Section> = serde_json::from_str(content).map_err(|e| e.to_string())?;
+    if catalogue.is_empty() {
+        return Err("no sections defined".to_string());
+    }
+
+    for (i, section) in catalogue.iter().enumerate() {
+        let words: Vec<&str> = section.name.split(' ').collect();
+        let valid = words
+            .iter()
+            .all(|word| !word.is_empty() && word.chars().all(|c| c.is_lowercase() || c == '-'));
+        if !valid {
+            return Err(format!(
+                "invalid section name \"{}\" (expected lowercase words separated by single spaces)",
+                section.name
+            ));
+        }
+        // `show patient` would shadow the section
+        if words[0] == "patient" {
+            return Err(format!("section name \"{}\" clashes with the patient commands", section.name));
+        }
+        // `register allergy` would shadow the section
+        if words[0] == "allergy" || words[0] == "allergies" {
+            return Err(format!("section name \"{}\" clashes with register allergy", section.name));
+        }
+        if catalogue[..i].iter().any(|other| other.name == section.name) {
+            return Err(format!("section \"{}\" is defined twice", section.name));
+        }
+    }
+
+    for (export, label) in [(Export::Reason, "reason"), (Export::Condition, "condition")] {
+        let names: Vec<&str> = catalogue
+            .iter()
+            .filter(|section| section.export == export)
+            .map(|section| section.name.as_str())
+            .collect();
+        if names.len() > 1 {
+            return Err(format!("only one section can be exported as {}: {}", label, names.join(", ")));
+        }
+    }
+    Ok(catalogue)
+}
+
+// Load a catalogue from a JSON file such as
+// [{"name": "complaint", "soap": "subjective", "export": "reason"}]
+pub fn load(path: &Path) -> Result<Vec<Section>, String> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read sections {}: {}", path.display(), e))?;
+    parse(&content).map_err(|e| format!("Invalid sections {}: {}", path.display(), e))
+}
+
+pub fn set_catalogue(catalogue: Vec<Section>) {
+    *CATALOGUE.lock().unwrap() = catalogue;
+}
+
+// Every section, in clinical order
+pub fn all() -> Vec<Section> {
+    CATALOGUE.lock().unwrap().clone()
+}
+
+pub fn names() -> Vec<String> {
+    CATALOGUE.lock().unwrap().iter().map(|section| section.name.clone()).collect()
+}
+
+pub fn is_known(name: &str) -> bool {
+    CATALOGUE.lock().unwrap().iter().any(|section| section.name == name)
+}
+
+// Position of a section in the catalogue; unknown sections sort last
+pub fn position(name: &str) -> usize {
+    let catalogue = CATALOGUE.lock().unwrap();
+    catalogue
+        .iter()
+        .position(|section| section.name == name)
+        .unwrap_or(catalogue.len())
+}
+
+fn exported_as(export: Export) -> Option<String> {
+    CATALOGUE
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|section| section.export == export)
+        .map(|section| section.name.clone())
+}
+
+// Section holding the chief complaint, if the catalogue has one
+pub fn reason_section() -> Option<String> {
+    exported_as(Export::Reason)
+}
+
+// Section holding the diagnoses, if the catalogue has one
+pub fn diagnosis_section() -> Option<String> {
+    exported_as(Export::Condition)
+}
+
+// Section holding the examination findings: the first one reviewed under
+// Objective, if the catalogue has one
+pub fn exam_section() -> Option<String> {
+    CATALOGUE
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|section| section.soap == Soap::Objective)
+        .map(|section| section.name.clone())
+}
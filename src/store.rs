@@ -0,0 +1,174 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+const PREFIX: &str = "encounter_";
+const EXTENSION: &str = ".med";
+
+// Durable storage for committed encounters. IDs are allocated by scanning the
+// output directory, so numbering carries on across sessions, and existing
+// files are never overwritten.
+pub struct EncounterStore {
+    dir: PathBuf,
+}
+
+impl EncounterStore {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    // Encounter number and version encoded in a file name: `encounter_12.med`
+    // is version 1 and `encounter_12.v2.med` its first addendum
+    pub fn parse_name(file_name: &str) -> Option<(usize, u32)> {
+        let stem = file_name.strip_prefix(PREFIX)?.strip_suffix(EXTENSION)?;
+        match stem.split_once(".v") {
+            Some((id, version)) => {
+                let version = version.parse().ok().filter(|version| *version > 1)?;
+                Some((id.parse().ok()?, version))
+            }
+            None => Some((stem.parse().ok()?, 1)),
+        }
+    }
+
+    pub fn file_name(id: usize, version: u32) -> String {
+        if version == 1 {
+            format!("{}{}{}", PREFIX, id, EXTENSION)
+        } else {
+            format!("{}{}.v{}{}", PREFIX, id, version, EXTENSION)
+        }
+    }
+
+    pub fn path(&self, id: usize, version: u32) -> PathBuf {
+        self.dir.join(Self::file_name(id, version))
+    }
+
+    // Latest version of every committed encounter with its number, in numeric
+    // order. Earlier versions stay on disk but are superseded by their addenda.
+    pub fn list(&self) -> Result<Vec<(usize, PathBuf)>, String> {
+        Ok(self
+            .versions()?
+            .into_iter()
+            .map(|(id, (_, path))| (id, path))
+            .collect())
+    }
+
+    // Latest version of each encounter number, with its file
+    fn versions(&self) -> Result<BTreeMap<usize, (u32, PathBuf)>, String> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(BTreeMap::new()),
+            Err(e) => return Err(format!("Failed to read {}: {}", self.dir.display(), e)),
+        };
+
+        let mut versions: BTreeMap<usize, (u32, PathBuf)> = BTreeMap::new();
+        for entry in entries.filter_map(|entry| entry.ok()) {
+            let Some((id, version)) = entry.file_name().to_str().and_then(Self::parse_name) else {
+                continue;
+            };
+            if versions.get(&id).is_none_or(|(latest, _)| version > *latest) {
+                versions.insert(id, (version, entry.path()));
+            }
+        }
+        Ok(versions)
+    }
+
+    // Highest version committed for an encounter number
+    pub fn latest_version(&self, id: usize) -> Result<Option<u32>, String> {
+        Ok(self.versions()?.get(&id).map(|(version, _)| *version))
+    }
+
+    // Next free encounter number: one past the highest number already on disk
+    pub fn next_id(&self) -> Result<usize, String> {
+        let highest = self.list()?.last().map_or(0, |(id, _)| *id);
+        Ok(highest + 1)
+    }
+
+    // Allocate an ID and write the record produced by `render` for it. If another
+    // session claims the same ID first, the next one is tried instead.
+    pub fn save_new<F>(&self, render: F) -> Result<(usize, PathBuf), String>
+    where
+        F: Fn(usize) -> Result<String, String>,
+    {
+        fs::create_dir_all(&self.dir)
+            .map_err(|e| format!("Failed to create {}: {}", self.dir.display(), e))?;
+
+        let mut id = self.next_id()?;
+        loop {
+            let path = self.path(id, 1);
+            match self.write_atomic(&path, &render(id)?) {
+                Ok(()) => return Ok((id, path)),
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => id += 1,
+                Err(e) => return Err(format!("Failed to write {}: {}", path.display(), e)),
+            }
+        }
+    }
+
+    // Write version `version` of encounter `id`. Versions are never
+    // overwritten, so an addendum committed twice fails instead of replacing
+    // the first one.
+    pub fn save_version(&self, id: usize, version: u32, contents: &str) -> Result<PathBuf, String> {
+        let path = self.path(id, version);
+        match self.write_atomic(&path, contents) {
+            Ok(()) => Ok(path),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => Err(format!(
+                "{} already exists; encounter {} was amended elsewhere, reopen its latest version",
+                path.display(),
+                id
+            )),
+            Err(e) => Err(format!("Failed to write {}: {}", path.display(), e)),
+        }
+    }
+
+    // Write a record without ever clobbering one. Unlike rename, hard_link
+    // fails when the target exists.
+    fn write_atomic(&self, path: &Path, contents: &str) -> std::io::Result<()> {
+        if path.exists() {
+            return Err(ErrorKind::AlreadyExists.into());
+        }
+        write_through_temp(path, contents, |temp_path| fs::hard_link(temp_path, path))
+    }
+}
+
+// Temporary files get the process ID and a counter in their name, so two
+// sessions or two saves never share one
+static TEMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+// Write `contents` to a temporary file next to `path`, flush it to disk, then
+// move it into place with `place`. A crash never leaves a truncated file
+// behind.
+fn write_through_temp(
+    path: &Path,
+    contents: &str,
+    place: impl FnOnce(&Path) -> std::io::Result<()>,
+) -> std::io::Result<()> {
+    let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let temp_path = dir.join(format!(
+        ".{}.{}.{}.tmp",
+        path.file_name().and_then(|name| name.to_str()).unwrap_or("file"),
+        std::process::id(),
+        TEMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+
+    let result = (|| {
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&temp_path)?;
+        file.write_all(contents.as_bytes())?;
+        file.sync_all()?;
+        place(&temp_path)?;
+        // Persist the new directory entry as well
+        File::open(dir)?.sync_all()
+    })();
+
+    let _ = fs::remove_file(&temp_path);
+    result
+}
+
+// Replace a file as a whole, so an interrupted save keeps the previous
+// contents intact. Used for drafts, the patient registry and macros.
+pub fn replace_atomic(path: &Path, contents: &str) -> std::io::Result<()> {
+    write_through_temp(path, contents, |temp_path| fs::rename(temp_path, path))
+}
@@ -0,0 +1,177 @@
+use crate::clinical::ClinicalAction;
+use crate::command::Command;
+use crate::parser;
+use crate::sections;
+use serde::Deserialize;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+
+// Default section text and routine orders for a kind of visit, stored as
+// <name>.json in the templates directory
+pub struct Template {
+    pub name: String,
+    pub description: String,
+    pub sections: Vec<TemplateSection>,
+    pub orders: Vec<ClinicalAction>,
+}
+
+// Text added to a section when the template is applied. It may contain
+// {{placeholders}} that must be filled in before the encounter is committed.
+#[derive(Deserialize)]
+pub struct TemplateSection {
+    pub section: String,
+    pub text: String,
+}
+
+// Template file as written, with orders still in command syntax
+#[derive(Deserialize)]
+struct TemplateFile {
+    #[serde(default)]
+    description: String,
+    #[serde(default)]
+    sections: Vec<TemplateSection>,
+    // prescribe, request or referral commands, e.g. "request \"HbA1c\""
+    #[serde(default)]
+    orders: Vec<String>,
+}
+
+// Template names are file names without the .json extension
+fn valid_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+// Names of the templates in `dir`, sorted
+pub fn names(dir: &Path) -> Result<Vec<String>, String> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(format!("Failed to read {}: {}", dir.display(), e)),
+    };
+    let mut names: Vec<String> = entries
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            if path.extension()? != "json" {
+                return None;
+            }
+            let name = path.file_stem()?.to_str()?.to_string();
+            valid_name(&name).then_some(name)
+        })
+        .collect();
+    names.sort();
+    Ok(names)
+}
+
+// An order line of a template, which must be a prescribe, request or
+// referral command
+fn parse_order(order: &str) -> Result<ClinicalAction, String> {
+    if !placeholders(order).is_empty() {
+        return Err(format!("order \"{}\" must not contain placeholders", order));
+    }
+    match parser::parse(order) {
+        Ok(Command::Prescribe(prescription)) if prescription.allergy_override.is_some() => {
+            Err(format!("order \"{}\" must not override allergies", order))
+        }
+        Ok(Command::Prescribe(prescription)) => Ok(ClinicalAction::Prescription(prescription)),
+        Ok(Command::Request(exam)) => Ok(ClinicalAction::ExamRequest(exam)),
+        Ok(Command::Referral(referral)) => Ok(ClinicalAction::Referral(referral)),
+        Ok(_) => Err(format!("order \"{}\" is not a prescribe, request or referral command", order)),
+        Err(e) => Err(format!("order \"{}\": {}", order, e.message)),
+    }
+}
+
+pub fn load(dir: &Path, name: &str) -> Result<Template, String> {
+    let available = || match names(dir) {
+        Ok(names) if !names.is_empty() => format!("available: {}", names.join(", ")),
+        _ => format!("no templates in {}", dir.display()),
+    };
+    if !valid_name(name) {
+        return Err(format!("Invalid template name \"{}\" ({})", name, available()));
+    }
+
+    let path = dir.join(format!("{}.json", name));
+    let content = match fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            return Err(format!("Unknown template \"{}\" ({})", name, available()))
+        }
+        Err(e) => return Err(format!("Failed to read {}: {}", path.display(), e)),
+    };
+    let file: TemplateFile =
+        serde_json::from_str(&content).map_err(|e| format!("Invalid template {}: {}", path.display(), e))?;
+
+    for section in &file.sections {
+        if !sections::is_known(&section.section) {
+            return Err(format!(
+                "Invalid template {}: unknown section \"{}\" (expected {})",
+                path.display(),
+                section.section,
+                sections::names().join(", ")
+            ));
+        }
+        // Diagnoses are ICD-10 coded as the template is applied
+        if sections::diagnosis_section().as_deref() == Some(section.section.as_str())
+            && !placeholders(&section.text).is_empty()
+        {
+            return Err(format!(
+                "Invalid template {}: the {} section must not contain placeholders",
+                path.display(),
+                section.section
+            ));
+        }
+    }
+    let orders = file
+        .orders
+        .iter()
+        .map(|order| parse_order(order))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Invalid template {}: {}", path.display(), e))?;
+
+    Ok(Template {
+        name: name.to_string(),
+        description: file.description,
+        sections: file.sections,
+        orders,
+    })
+}
+
+// Names of the {{placeholders}} in a text, in order of appearance
+pub fn placeholders(text: &str) -> Vec<String> {
+    let mut found = Vec::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("{{") {
+        let Some(end) = rest[start + 2..].find("}}") else {
+            break;
+        };
+        let name = rest[start + 2..start + 2 + end].trim().to_string();
+        if !found.contains(&name) {
+            found.push(name);
+        }
+        rest = &rest[start + 2 + end + 2..];
+    }
+    found
+}
+
+// Replace every {{placeholder}} with this name, ignoring spaces inside the
+// braces. Returns None when the text has no such placeholder.
+pub fn fill(text: &str, placeholder: &str, value: &str) -> Option<String> {
+    let mut filled = String::new();
+    let mut rest = text;
+    let mut replaced = false;
+    while let Some(start) = rest.find("{{") {
+        let Some(end) = rest[start + 2..].find("}}") else {
+            break;
+        };
+        let name = &rest[start + 2..start + 2 + end];
+        filled.push_str(&rest[..start]);
+        if name.trim().eq_ignore_ascii_case(placeholder.trim()) {
+            filled.push_str(value);
+            replaced = true;
+        } else {
+            filled.push_str(&rest[start..start + 2 + end + 2]);
+        }
+        rest = &rest[start + 2 + end + 2..];
+    }
+    filled.push_str(rest);
+    replaced.then_some(filled)
+}
@@ -0,0 +1,158 @@
+use crate::appointment::Encounter;
+use crate::clinical::ClinicalAction;
+use crate::sections;
+use serde::Deserialize;
+use std::fmt;
+use std::path::Path;
+
+// How a failed rule affects `commit encounter`
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    // Blocks the commit
+    Error,
+    // Blocks the commit unless it is forced
+    Warning,
+    // Rule is disabled
+    Off,
+}
+
+// When a rule applies
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Trigger {
+    Always,
+    // The encounter has at least one prescription
+    Prescription,
+    // The encounter has any prescription, exam request or referral
+    Orders,
+    // The appointment is not a virtual visit
+    InPerson,
+}
+
+// A section that must have at least one entry before the encounter is committed
+#[derive(Debug, Clone, Deserialize)]
+pub struct Rule {
+    pub require: String,
+    #[serde(default = "always")]
+    pub when: Trigger,
+    pub severity: Severity,
+}
+
+fn always() -> Trigger {
+    Trigger::Always
+}
+
+// A rule the encounter does not satisfy
+pub struct Finding {
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let label = match self.severity {
+            Severity::Error => "error",
+            _ => "warning",
+        };
+        write!(f, "{}: {}", label, self.message)
+    }
+}
+
+// Rules used unless a rules file is given with --rules. They require the
+// complaint, examination and diagnosis sections of the section catalogue, the
+// ones it has.
+pub fn default_rules() -> Vec<Rule> {
+    let rule = |require: &str, when, severity| Rule {
+        require: require.to_string(),
+        when,
+        severity,
+    };
+    let mut rules = Vec::new();
+    if let Some(complaint) = sections::reason_section() {
+        rules.push(rule(&complaint, Trigger::Always, Severity::Warning));
+    }
+    if let Some(exam) = sections::exam_section() {
+        rules.push(rule(&exam, Trigger::InPerson, Severity::Warning));
+    }
+    if let Some(diagnosis) = sections::diagnosis_section() {
+        rules.push(rule(&diagnosis, Trigger::Prescription, Severity::Error));
+        rules.push(rule(&diagnosis, Trigger::Orders, Severity::Warning));
+    }
+    rules
+}
+
+// Load a rule set from a JSON array such as
+// [{"require": "physical exam", "when": "in-person", "severity": "error"}]
+pub fn load_rules(path: &Path) -> Result<Vec<Rule>, String> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read rules {}: {}", path.display(), e))?;
+    let rules: Vec<Rule> = serde_json::from_str(&content)
+        .map_err(|e| format!("Invalid rules {}: {}", path.display(), e))?;
+
+    for rule in &rules {
+        if !sections::is_known(&rule.require) {
+            return Err(format!(
+                "Invalid rules {}: unknown section \"{}\" (expected {})",
+                path.display(),
+                rule.require,
+                sections::names().join(", ")
+            ));
+        }
+    }
+    Ok(rules)
+}
+
+fn applies(trigger: Trigger, encounter: &Encounter) -> bool {
+    match trigger {
+        Trigger::Always => true,
+        Trigger::Prescription => encounter
+            .actions
+            .iter()
+            .any(|action| matches!(action, ClinicalAction::Prescription(_))),
+        Trigger::Orders => !encounter.actions.is_empty(),
+        Trigger::InPerson => !encounter.virtual_visit,
+    }
+}
+
+// Every rule the encounter fails, errors first. A section is only reported
+// once, at its most severe failing rule. Template placeholders left unfilled
+// are always errors.
+pub fn check(rules: &[Rule], encounter: &Encounter) -> Vec<Finding> {
+    let mut failed: Vec<&Rule> = rules
+        .iter()
+        .filter(|rule| rule.severity != Severity::Off)
+        .filter(|rule| applies(rule.when, encounter))
+        .filter(|rule| encounter.sections.get(&rule.require).is_none_or(|entries| entries.is_empty()))
+        .collect();
+    failed.sort_by_key(|rule| rule.severity != Severity::Error);
+    let mut reported: Vec<&str> = Vec::new();
+    let mut findings: Vec<Finding> = encounter
+        .unfilled_placeholders()
+        .into_iter()
+        .map(|(section, names)| {
+            let names: Vec<String> = names.iter().map(|name| format!("{{{{{}}}}}", name)).collect();
+            Finding {
+                severity: Severity::Error,
+                message: format!("{} has unfilled placeholders: {}", section, names.join(", ")),
+            }
+        })
+        .collect();
+    for rule in failed {
+        if reported.contains(&rule.require.as_str()) {
+            continue;
+        }
+        reported.push(&rule.require);
+        let condition = match rule.when {
+            Trigger::Always => "",
+            Trigger::Prescription => " when a prescription is written",
+            Trigger::Orders => " when orders are placed",
+            Trigger::InPerson => " for in-person visits",
+        };
+        findings.push(Finding {
+            severity: rule.severity,
+            message: format!("{} is required{}", rule.require, condition),
+        });
+    }
+    findings
+}